@@ -0,0 +1,59 @@
+//! Renders the comment posted on each issue of a release. The template can be
+//! replaced by the user; these placeholders are filled in:
+//!
+//! {version}: the name of the version released.
+//! {range}: the range of commits released, e.g. master..develop.
+//! {commits}: one line per commit mentioning the issue, with its short SHA and
+//! subject.
+
+use error::Error;
+use git::Commit;
+use std::fs::File;
+use std::io::Read;
+
+pub const DEFAULT_TEMPLATE: &'static str = "Released in version {version}.
+
+Commits in {range}:
+{commits}";
+
+/// Reads the template from the file, or returns the default one.
+pub fn read_template(path: Option<&str>) -> Result<String, Error> {
+    let path = match path {
+        Some(path) => path,
+        None => return Ok(DEFAULT_TEMPLATE.to_owned())
+    };
+    let mut template = String::new();
+    try!(
+        File::open(path)
+            .and_then(|mut file| file.read_to_string(&mut template))
+            .map_err(|e| Error::Io(format!("Could not read comment template \
+                {}", path), e))
+    );
+    Ok(template)
+}
+
+pub fn render(template: &str, version_name: &str, range: &str,
+        commits: &[&Commit]) -> String {
+    let lines: Vec<String> = commits
+        .iter()
+        .map(|commit| format!("* {} {}", commit.short_sha(), commit.summary))
+        .collect();
+    template
+        .replace("{version}", version_name)
+        .replace("{range}", range)
+        .replace("{commits}", &lines.join("\n"))
+}
+
+#[test]
+fn default_template() {
+    let first = ::git::test_commit("0123456789abcdef", "[EX-1] first", "");
+    let second = ::git::test_commit("fedcba9876543210", "[EX-1] second", "");
+    assert_eq!(render(DEFAULT_TEMPLATE, "1.2.0", "master..develop",
+        &[&second, &first]), "Released in version 1.2.0.
+
+Commits in master..develop:
+* fedcba9 [EX-1] second
+* 0123456 [EX-1] first");
+    assert_eq!(render("{version} {unknown}", "1.2.0", "", &[]),
+        "1.2.0 {unknown}");
+}
@@ -0,0 +1,156 @@
+//! Errors which can end a run. Every kind of error exits the process with its
+//! own code so that scripts can tell them apart:
+//!
+//! 2: Config, the arguments or a config file are invalid.
+//! 3: Git, the repository, a revision or the commits could not be read.
+//! 4: Http, Jira could not be reached or answered with an unexpected status.
+//! 5: Auth, Jira rejected the credentials (401 or 403).
+//! 6: NotFound, the project or an other resource required does not exist.
+//! 7: Decode, a response from Jira or a journal could not be decoded.
+//! 8: Io, an output file such as the release notes could not be written.
+//!
+//! Exit code 1 is left to argument errors reported by clap and panics.
+
+use git2;
+use hyper;
+use hyper::status::StatusCode;
+use std::error;
+use std::fmt;
+use std::io;
+
+#[derive(Debug)]
+pub enum HttpCause {
+    Connection(hyper::Error),
+    Status(StatusCode)
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Git(String, git2::Error),
+    Http(String, HttpCause),
+    Auth(String, StatusCode),
+    NotFound(String, StatusCode),
+    Decode(String, Box<dyn error::Error + Send + Sync>),
+    Config(String),
+    Io(String, io::Error)
+}
+
+impl Error {
+    /// Picks the kind of error from the status of a failed response.
+    pub fn from_status(context: String, status: StatusCode) -> Error {
+        match status {
+            StatusCode::Unauthorized | StatusCode::Forbidden =>
+                Error::Auth(context, status),
+            StatusCode::NotFound => Error::NotFound(context, status),
+            _ => Error::Http(context, HttpCause::Status(status))
+        }
+    }
+
+    pub fn connection(context: String, err: hyper::Error) -> Error {
+        Error::Http(context, HttpCause::Connection(err))
+    }
+
+    /// A copy of the error, e.g. for every issue of a search which failed.
+    /// The kind, and so the exit code, and the message are kept, but an
+    /// underlying error is only kept as text.
+    pub fn duplicate(&self) -> Error {
+        match *self {
+            Error::Git(ref msg, ref e) =>
+                Error::Git(msg.clone(), git2::Error::from_str(e.message())),
+            Error::Http(ref msg, HttpCause::Connection(ref e)) => {
+                let e = io::Error::new(io::ErrorKind::Other, e.to_string());
+                Error::connection(msg.clone(), hyper::Error::Io(e))
+            },
+            Error::Http(ref msg, HttpCause::Status(status)) =>
+                Error::Http(msg.clone(), HttpCause::Status(status)),
+            Error::Auth(ref msg, status) => Error::Auth(msg.clone(), status),
+            Error::NotFound(ref msg, status) =>
+                Error::NotFound(msg.clone(), status),
+            Error::Decode(ref msg, ref e) =>
+                Error::Decode(msg.clone(), e.to_string().into()),
+            Error::Config(ref msg) => Error::Config(msg.clone()),
+            Error::Io(ref msg, ref e) =>
+                Error::Io(msg.clone(), io::Error::new(e.kind(), e.to_string()))
+        }
+    }
+
+    /// The code the process exits with, see the list above.
+    pub fn exit_code(&self) -> i32 {
+        match *self {
+            Error::Config(_) => 2,
+            Error::Git(_, _) => 3,
+            Error::Http(_, _) => 4,
+            Error::Auth(_, _) => 5,
+            Error::NotFound(_, _) => 6,
+            Error::Decode(_, _) => 7,
+            Error::Io(_, _) => 8
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Git(ref msg, ref e) => write!(f, "{}: {}", msg, e.message()),
+            Error::Http(ref msg, HttpCause::Connection(ref e)) =>
+                write!(f, "{}: {}", msg, e),
+            Error::Http(ref msg, HttpCause::Status(ref status)) |
+            Error::Auth(ref msg, ref status) |
+            Error::NotFound(ref msg, ref status) =>
+                write!(f, "{}: {}", msg, status),
+            Error::Decode(ref msg, ref e) => write!(f, "{}: {}", msg, e),
+            Error::Config(ref msg) => write!(f, "{}", msg),
+            Error::Io(ref msg, ref e) => write!(f, "{}: {}", msg, e)
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            Error::Git(_, ref e) => Some(e),
+            Error::Http(_, HttpCause::Connection(ref e)) => Some(e),
+            Error::Decode(_, ref e) => Some(&**e),
+            Error::Io(_, ref e) => Some(e),
+            _ => None
+        }
+    }
+}
+
+#[test]
+fn status_kinds() {
+    let auth = Error::from_status(String::new(), StatusCode::Unauthorized);
+    assert_eq!(auth.exit_code(), 5);
+    let forbidden = Error::from_status(String::new(), StatusCode::Forbidden);
+    assert_eq!(forbidden.exit_code(), 5);
+    let missing = Error::from_status(String::new(), StatusCode::NotFound);
+    assert_eq!(missing.exit_code(), 6);
+    let server =
+        Error::from_status(String::new(), StatusCode::InternalServerError);
+    assert_eq!(server.exit_code(), 4);
+    let gateway = Error::from_status(String::from("Error fetching versions"),
+        StatusCode::BadGateway);
+    assert_eq!(gateway.to_string(),
+        "Error fetching versions: 502 Bad Gateway");
+}
+
+#[test]
+fn duplicated() {
+    let errors = [
+        Error::Git(String::from("Git"), git2::Error::from_str("no repo")),
+        Error::connection(String::from("Http"), hyper::Error::Io(
+            io::Error::new(io::ErrorKind::Other, "refused"))),
+        Error::from_status(String::from("Status"), StatusCode::BadGateway),
+        Error::from_status(String::from("Auth"), StatusCode::Forbidden),
+        Error::from_status(String::from("Missing"), StatusCode::NotFound),
+        Error::Decode(String::from("Decode"), "bad json".into()),
+        Error::Config(String::from("Config")),
+        Error::Io(String::from("Io"),
+            io::Error::new(io::ErrorKind::NotFound, "no file"))
+    ];
+    for error in &errors {
+        let copy = error.duplicate();
+        assert_eq!(copy.exit_code(), error.exit_code());
+        assert_eq!(copy.to_string(), error.to_string());
+    }
+}
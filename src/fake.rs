@@ -0,0 +1,341 @@
+//! An in-memory `JiraApi`, to test code built on the crate without a Jira
+//! instance. Versions and issues are added up front, then the state left by a
+//! release can be inspected:
+//!
+//! ```
+//! use jira_releaser::{FakeJira, ReleasePlan};
+//! use jira_releaser::journal::Journal;
+//!
+//! let jira = FakeJira::new();
+//! jira.add_issue("FOO-1", "Fix the thing");
+//! let plan = ReleasePlan::new(vec![String::from("FOO")], "1.2.0");
+//! let keys = [String::from("FOO-1")];
+//! plan.publish(&jira, &Journal::disabled(), &keys, None).unwrap();
+//! let fields = jira.issue("FOO-1").unwrap();
+//! assert_eq!(fields.fixVersions[0].name, "1.2.0");
+//! ```
+
+use error::Error;
+use hyper::status::StatusCode;
+use jira::{JiraApi, JiraIssueFields, JiraNamed, JiraTransition, JiraVersion,
+    pick_version};
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+struct FakeVersion {
+    project_id: String,
+    version: JiraVersion,
+    release_date: Option<String>
+}
+
+struct FakeIssue {
+    fields: JiraIssueFields,
+    transitions: Vec<JiraTransition>,
+    comments: Vec<String>
+}
+
+struct State {
+    versions: Vec<FakeVersion>,
+    issues: HashMap<String, FakeIssue>,
+    // Requests about these issues fail with the status.
+    failures: HashMap<String, StatusCode>,
+    // Searches including these issues fail with the status.
+    search_failures: HashMap<String, StatusCode>,
+    // Transitions of these issues fail with the status.
+    transition_failures: HashMap<String, StatusCode>,
+    next_id: u64
+}
+
+/// Holds the versions and issues of a fake Jira. It can be shared between
+/// threads like `JiraClient`.
+pub struct FakeJira {
+    state: Mutex<State>
+}
+
+fn named(name: &str) -> JiraNamed {
+    JiraNamed { name: name.to_owned() }
+}
+
+fn version_not_found<T>(version: &JiraVersion) -> Result<T, Error> {
+    Err(Error::from_status(format!("Server error with Jira version {}",
+        version.name), StatusCode::NotFound))
+}
+
+impl State {
+    fn add_version(&mut self, project_id: &str, name: &str) -> JiraVersion {
+        self.next_id += 1;
+        let version = JiraVersion {
+            name: name.to_owned(),
+            id: self.next_id.to_string()
+        };
+        self.versions.push(FakeVersion {
+            project_id: project_id.to_owned(),
+            version: version.clone(),
+            release_date: None
+        });
+        version
+    }
+
+    fn version(&mut self, version: &JiraVersion)
+            -> Result<&mut FakeVersion, Error> {
+        match self.versions.iter_mut().find(|v| v.version.id == version.id) {
+            Some(found) => Ok(found),
+            None => version_not_found(version)
+        }
+    }
+
+    // The issue, or None if it doesn't exist. Fails if the issue was set to
+    // fail with fail_issue.
+    fn issue(&mut self, issue_token: &str)
+            -> Result<Option<&mut FakeIssue>, Error> {
+        if let Some(status) = self.failures.get(issue_token) {
+            return Err(Error::from_status(format!("Error with issue {}",
+                issue_token), *status));
+        }
+        Ok(self.issues.get_mut(issue_token))
+    }
+
+    fn existing_issue(&mut self, issue_token: &str)
+            -> Result<&mut FakeIssue, Error> {
+        match try!(self.issue(issue_token)) {
+            Some(issue) => Ok(issue),
+            None => Err(Error::from_status(format!("Error with issue {}",
+                issue_token), StatusCode::NotFound))
+        }
+    }
+}
+
+impl FakeJira {
+    /// A Jira without any version or issue.
+    pub fn new() -> FakeJira {
+        FakeJira {
+            state: Mutex::new(State {
+                versions: Vec::new(),
+                issues: HashMap::new(),
+                failures: HashMap::new(),
+                search_failures: HashMap::new(),
+                transition_failures: HashMap::new(),
+                next_id: 10000
+            })
+        }
+    }
+
+    /// Adds an unreleased version to the project.
+    pub fn add_version(&self, project_id: &str, name: &str) -> JiraVersion {
+        self.state.lock().unwrap().add_version(project_id, name)
+    }
+
+    /// Adds a task in the `To Do` status, without fix versions.
+    pub fn add_issue(&self, issue_token: &str, summary: &str) {
+        self.add_issue_fields(issue_token, JiraIssueFields {
+            fixVersions: Vec::new(),
+            summary: summary.to_owned(),
+            issuetype: named("Task"),
+            status: named("To Do"),
+            priority: None
+        });
+    }
+
+    pub fn add_issue_fields(&self, issue_token: &str,
+            fields: JiraIssueFields) {
+        self.state.lock().unwrap().issues.insert(issue_token.to_owned(),
+            FakeIssue {
+                fields: fields,
+                transitions: Vec::new(),
+                comments: Vec::new()
+            });
+    }
+
+    /// Makes the transition available on the issue, whatever its status.
+    /// Doing it moves the issue to the status `to`.
+    pub fn add_transition(&self, issue_token: &str, name: &str, to: &str) {
+        let mut state = self.state.lock().unwrap();
+        let issue = state.issues.get_mut(issue_token)
+            .expect("Transition added to an unknown issue");
+        let id = (issue.transitions.len() + 1).to_string();
+        issue.transitions.push(JiraTransition {
+            id: id,
+            name: name.to_owned(),
+            to: named(to)
+        });
+    }
+
+    /// Makes every request about the issue fail as if Jira answered with
+    /// the status, except searches which still return it.
+    pub fn fail_issue(&self, issue_token: &str, status: StatusCode) {
+        self.state.lock().unwrap().failures.insert(issue_token.to_owned(),
+            status);
+    }
+
+    /// Makes every search including the issue fail as if Jira answered with
+    /// the status.
+    pub fn fail_search(&self, issue_token: &str, status: StatusCode) {
+        self.state.lock().unwrap().search_failures
+            .insert(issue_token.to_owned(), status);
+    }
+
+    /// Makes the issue work again after `fail_issue`, e.g. to run a release
+    /// again once Jira is back.
+    pub fn heal_issue(&self, issue_token: &str) {
+        self.state.lock().unwrap().failures.remove(issue_token);
+    }
+
+    /// Makes the transitions of the issue fail as if Jira answered with the
+    /// status, e.g. 400 when a validator refuses it.
+    pub fn fail_transition(&self, issue_token: &str, status: StatusCode) {
+        self.state.lock().unwrap().transition_failures
+            .insert(issue_token.to_owned(), status);
+    }
+
+    /// The versions of the project, in the order they were added.
+    pub fn versions(&self, project_id: &str) -> Vec<JiraVersion> {
+        self.state.lock().unwrap().versions
+            .iter()
+            .filter(|v| v.project_id == project_id)
+            .map(|v| v.version.clone())
+            .collect()
+    }
+
+    /// The date the version was released on, None if it isn't released.
+    pub fn release_date(&self, version: &JiraVersion) -> Option<String> {
+        self.state.lock().unwrap().versions
+            .iter()
+            .find(|v| v.version.id == version.id)
+            .and_then(|v| v.release_date.clone())
+    }
+
+    /// The current fields of the issue, None if it doesn't exist.
+    pub fn issue(&self, issue_token: &str) -> Option<JiraIssueFields> {
+        self.state.lock().unwrap().issues
+            .get(issue_token)
+            .map(|issue| issue.fields.clone())
+    }
+
+    /// The comments posted on the issue, the oldest first.
+    pub fn comments(&self, issue_token: &str) -> Vec<String> {
+        self.state.lock().unwrap().issues
+            .get(issue_token)
+            .map_or(Vec::new(), |issue| issue.comments.clone())
+    }
+}
+
+impl JiraApi for FakeJira {
+    fn create_version(&self, project_id: &str, name: &str)
+            -> Result<JiraVersion, Error> {
+        Ok(self.add_version(project_id, name))
+    }
+
+    fn find_version(&self, project_id: &str, name: &str)
+            -> Result<Option<JiraVersion>, Error> {
+        pick_version(project_id, name, self.versions(project_id))
+    }
+
+    fn release_version(
+            &self,
+            version: &JiraVersion,
+            release_date: &str,
+            _description: Option<&str>
+            ) -> Result<(), Error> {
+        let mut state = self.state.lock().unwrap();
+        let found = try!(state.version(version));
+        found.release_date = Some(release_date.to_owned());
+        Ok(())
+    }
+
+    fn unrelease_version(&self, version: &JiraVersion) -> Result<(), Error> {
+        let mut state = self.state.lock().unwrap();
+        let found = try!(state.version(version));
+        found.release_date = None;
+        Ok(())
+    }
+
+    fn version_issue_count(&self, version: &JiraVersion)
+            -> Result<u64, Error> {
+        let mut state = self.state.lock().unwrap();
+        try!(state.version(version));
+        let count = state.issues
+            .values()
+            .filter(|issue| {
+                issue.fields.fixVersions.iter().any(|v| v.id == version.id)
+            })
+            .count();
+        Ok(count as u64)
+    }
+
+    fn delete_version(&self, version: &JiraVersion) -> Result<(), Error> {
+        let mut state = self.state.lock().unwrap();
+        try!(state.version(version));
+        state.versions.retain(|v| v.version.id != version.id);
+        Ok(())
+    }
+
+    // A search is a single request, so it doesn't fail because of an issue
+    // set to fail with fail_issue, only with fail_search.
+    fn search_issues(&self, issue_tokens: &[String])
+            -> Result<HashMap<String, JiraIssueFields>, Error> {
+        let state = self.state.lock().unwrap();
+        let failure = issue_tokens
+            .iter()
+            .filter_map(|key| state.search_failures.get(key))
+            .next();
+        if let Some(status) = failure {
+            return Err(Error::from_status(String::from("Server error \
+                searching issues"), *status));
+        }
+        Ok(issue_tokens
+            .iter()
+            .filter_map(|key| state.issues.get(key).map(|issue| {
+                (key.clone(), issue.fields.clone())
+            }))
+            .collect())
+    }
+
+    fn add_issue_version(&self, issue_token: &str, version: &JiraVersion)
+            -> Result<(), Error> {
+        let mut state = self.state.lock().unwrap();
+        let issue = try!(state.existing_issue(issue_token));
+        let versions = &mut issue.fields.fixVersions;
+        if !versions.iter().any(|v| v.id == version.id) {
+            versions.push(version.clone());
+        }
+        Ok(())
+    }
+
+    fn remove_issue_version(&self, issue_token: &str, version_id: &str)
+            -> Result<bool, Error> {
+        let mut state = self.state.lock().unwrap();
+        match try!(state.issue(issue_token)) {
+            Some(issue) => {
+                issue.fields.fixVersions.retain(|v| v.id != version_id);
+                Ok(true)
+            },
+            None => Ok(false)
+        }
+    }
+
+    fn transitions(&self, issue_token: &str)
+            -> Result<Vec<JiraTransition>, Error> {
+        let mut state = self.state.lock().unwrap();
+        let issue = try!(state.existing_issue(issue_token));
+        Ok(issue.transitions.clone())
+    }
+
+    fn transition(&self, issue_token: &str, transition: &JiraTransition)
+            -> Result<(), Error> {
+        let mut state = self.state.lock().unwrap();
+        if let Some(status) = state.transition_failures.get(issue_token) {
+            return Err(Error::from_status(format!("Error transitioning \
+                issue {}", issue_token), *status));
+        }
+        let issue = try!(state.existing_issue(issue_token));
+        issue.fields.status = transition.to.clone();
+        Ok(())
+    }
+
+    fn comment(&self, issue_token: &str, body: &str) -> Result<(), Error> {
+        let mut state = self.state.lock().unwrap();
+        let issue = try!(state.existing_issue(issue_token));
+        issue.comments.push(body.to_owned());
+        Ok(())
+    }
+}
@@ -0,0 +1,342 @@
+//! Reads the commit logs through libgit2, so that the git binary doesn't need
+//! to be installed. Gives the same commits as `git log <to> ^<from>
+//! --no-merges`.
+
+use git2;
+use git2::{Oid, Repository, Sort};
+use error::Error;
+use std::cmp;
+use std::collections::HashMap;
+use std::fmt;
+use time;
+
+/// The commits reachable from `to` but not from `from`. Without `from`, this
+/// is the whole history of `to`.
+#[derive(RustcEncodable, Debug, Clone, PartialEq)]
+pub struct Range {
+    pub from: Option<String>,
+    pub to: String
+}
+
+impl fmt::Display for Range {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.from {
+            Some(ref from) => write!(f, "{}..{}", from, self.to),
+            None => write!(f, "{}", self.to)
+        }
+    }
+}
+
+/// A commit of the range, with its message split in the summary, the first
+/// paragraph, and the body.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Commit {
+    pub sha: String,
+    pub summary: String,
+    /// The rest of the message, including the trailers. Empty when the
+    /// message only has a summary.
+    pub body: String,
+    /// The name of the author.
+    pub author: String,
+    /// The date the commit was authored on, as YYYY-MM-DD.
+    pub date: String
+}
+
+impl Commit {
+    pub fn short_sha(&self) -> &str {
+        &self.sha[..cmp::min(7, self.sha.len())]
+    }
+}
+
+// The message after its first paragraph, which git2 gives as the summary.
+fn body(message: &str) -> String {
+    let message = message.trim_start();
+    match message.find("\n\n") {
+        Some(end) => message[end..].trim().to_owned(),
+        None => String::new()
+    }
+}
+
+// The date of the time as YYYY-MM-DD, in its own time zone.
+fn local_date(when: git2::Time) -> String {
+    let local = when.seconds() + i64::from(when.offset_minutes()) * 60;
+    let tm = time::at_utc(time::Timespec::new(local, 0));
+    tm.strftime("%Y-%m-%d").unwrap().to_string()
+}
+
+fn git_error(msg: String) -> impl FnOnce(git2::Error) -> Error {
+    move |e| Error::Git(msg, e)
+}
+
+fn resolve(repo: &Repository, revision: &str) -> Result<Oid, Error> {
+    repo.revparse_single(revision)
+        .and_then(|object| object.peel(git2::ObjectType::Commit))
+        .map(|commit| commit.id())
+        .map_err(git_error(format!("Could not find revision {}", revision)))
+}
+
+fn open(repo_path: &str) -> Result<Repository, Error> {
+    Repository::discover(repo_path)
+        .map_err(git_error(format!("Could not open git repository {}",
+            repo_path)))
+}
+
+/// Finds the closest tag matching the glob pattern, e.g. "v*", among the
+/// ancestors of `to`. A tag on `to` itself is skipped, since it is the release
+/// being made, as well as tags which don't point to a commit. Returns None
+/// when no tag matches.
+pub fn previous_tag(repo_path: &str, to: &str, pattern: &str)
+        -> Result<Option<String>, Error> {
+    let repo = try!(open(repo_path));
+    let names = try!(
+        repo.tag_names(Some(pattern))
+            .map_err(git_error("Could not list tags".to_owned()))
+    );
+    let mut names: Vec<&str> = names.iter().filter_map(|name| name).collect();
+    // Sorted by name, so the choice is stable when a commit has several.
+    names.sort();
+    let mut tags: HashMap<Oid, String> = HashMap::new();
+    for name in names {
+        match resolve(&repo, &(String::from("refs/tags/") + name)) {
+            Ok(oid) => {
+                tags.entry(oid).or_insert_with(|| name.to_owned());
+            },
+            Err(e) => warn!("Skipping tag {}: {}", name, e)
+        }
+    }
+    if tags.is_empty() {
+        return Ok(None);
+    }
+
+    let to_oid = try!(resolve(&repo, to));
+    let mut walk = try!(
+        repo.revwalk().map_err(git_error("Could not walk commits".to_owned()))
+    );
+    walk.set_sorting(Sort::TOPOLOGICAL | Sort::TIME);
+    try!(
+        walk.push(to_oid)
+            .map_err(git_error(format!("Could not walk {}", to)))
+    );
+    for oid in walk {
+        let oid = try!(oid.map_err(git_error("Could not walk".to_owned())));
+        if oid == to_oid {
+            continue;
+        }
+        if let Some(name) = tags.remove(&oid) {
+            return Ok(Some(name));
+        }
+    }
+    Ok(None)
+}
+
+/// Picks the commits to release, up to `to`. They start after `from` when it
+/// is given, otherwise after the closest tag matching `previous_tag`,
+/// otherwise after the release branch. The whole history of `to` is only
+/// released with `all_commits`: instead of the release branch, or when no tag
+/// matches `previous_tag`, which is a config error otherwise.
+pub fn pick_range(
+        repo_path: &str,
+        to: &str,
+        from: Option<&str>,
+        previous_tag: Option<&str>,
+        release_branch: &str,
+        all_commits: bool
+        ) -> Result<Range, Error> {
+    let from = match (from, previous_tag) {
+        (Some(from), _) => Some(from.to_owned()),
+        (None, Some(pattern)) => {
+            match try!(self::previous_tag(repo_path, to, pattern)) {
+                Some(tag) => Some(tag),
+                None if all_commits => None,
+                None => return Err(Error::Config(format!("No tag matching {} \
+                    before {}. Give the first commit to release with --from, \
+                    or release the whole history with --all-commits",
+                    pattern, to)))
+            }
+        },
+        (None, None) if all_commits => None,
+        (None, None) => Some(release_branch.to_owned())
+    };
+    Ok(Range {
+        from: from,
+        to: to.to_owned()
+    })
+}
+
+/// The date of the commit, as YYYY-MM-DD in the committer's time zone.
+pub fn commit_date(repo_path: &str, revision: &str) -> Result<String, Error> {
+    let repo = try!(open(repo_path));
+    let oid = try!(resolve(&repo, revision));
+    let commit = try!(
+        repo.find_commit(oid)
+            .map_err(git_error(format!("Could not read commit {}", revision)))
+    );
+    Ok(local_date(commit.time()))
+}
+
+/// Returns every commit in the range, newest first.
+pub fn logs(repo_path: &str, range: &Range) -> Result<Vec<Commit>, Error> {
+    let repo = try!(open(repo_path));
+    let mut walk = try!(
+        repo.revwalk().map_err(git_error("Could not walk commits".to_owned()))
+    );
+    walk.set_sorting(Sort::TIME);
+    try!(
+        walk.push(try!(resolve(&repo, &range.to)))
+            .map_err(git_error(format!("Could not walk {}", range.to)))
+    );
+    if let Some(ref from) = range.from {
+        try!(
+            walk.hide(try!(resolve(&repo, from)))
+                .map_err(git_error(format!("Could not walk {}", from)))
+        );
+    }
+
+    let mut logs = Vec::new();
+    for oid in walk {
+        let commit = try!(
+            oid.and_then(|oid| repo.find_commit(oid))
+                .map_err(git_error("Could not read commit".to_owned()))
+        );
+        if commit.parent_count() > 1 {
+            continue;
+        }
+        let summary = commit.summary_bytes().unwrap_or(b"");
+        let message = String::from_utf8_lossy(commit.message_bytes());
+        let author = commit.author();
+        logs.push(Commit {
+            sha: commit.id().to_string(),
+            summary: String::from_utf8_lossy(summary).into_owned(),
+            body: body(&message),
+            author: String::from_utf8_lossy(author.name_bytes()).into_owned(),
+            date: local_date(author.when())
+        });
+    }
+    Ok(logs)
+}
+
+#[cfg(test)]
+fn commit(repo: &Repository, branch: &str, message: &str, parents: &[Oid])
+        -> Oid {
+    // 2016-07-02 23:00 in UTC-4, which is already the next day in UTC.
+    let when = git2::Time::new(1467514800, -240);
+    let signature =
+        git2::Signature::new("Tester", "test@test.com", &when).unwrap();
+    let tree_id = repo.treebuilder(None).unwrap().write().unwrap();
+    let tree = repo.find_tree(tree_id).unwrap();
+    let parents: Vec<git2::Commit> = parents
+        .iter()
+        .map(|oid| repo.find_commit(*oid).unwrap())
+        .collect();
+    let parent_refs: Vec<&git2::Commit> = parents.iter().collect();
+    let reference = String::from("refs/heads/") + branch;
+    repo.commit(Some(&reference), &signature, &signature, message, &tree,
+        &parent_refs).unwrap()
+}
+
+#[cfg(test)]
+fn summaries(commits: Vec<Commit>) -> Vec<String> {
+    commits.into_iter().map(|commit| commit.summary).collect()
+}
+
+#[cfg(test)]
+fn range(from: Option<&str>, to: &str) -> Range {
+    Range {
+        from: from.map(String::from),
+        to: to.to_owned()
+    }
+}
+
+/// A commit authored by Tester on 2016-07-02, for the tests.
+#[cfg(test)]
+pub fn test_commit(sha: &str, summary: &str, body: &str) -> Commit {
+    Commit {
+        sha: sha.to_owned(),
+        summary: summary.to_owned(),
+        body: body.to_owned(),
+        author: String::from("Tester"),
+        date: String::from("2016-07-02")
+    }
+}
+
+#[cfg(test)]
+fn test_repo(name: &str) -> (::std::path::PathBuf, Repository) {
+    let path = ::std::env::temp_dir().join(
+        format!("jira-releaser-{}-{}", name, ::std::process::id())
+    );
+    let repo = Repository::init(&path).unwrap();
+    (path, repo)
+}
+
+#[test]
+fn branch_logs() {
+    let (path, repo) = test_repo("branch-logs");
+    let root = commit(&repo, "master", "[EX-1] root", &[]);
+    let feature = commit(&repo, "feature", "[EX-2] feature", &[root]);
+    let develop = commit(&repo, "develop", "[EX-3] develop", &[root]);
+    let merge = commit(&repo, "develop", "Merge feature", &[develop, feature]);
+    commit(&repo, "develop", "[EX-4] latest\n\nbody\n\nRefs: EX-5\n",
+        &[merge]);
+
+    let path_str = path.to_str().unwrap();
+    let found =
+        logs(path_str, &range(Some("master"), "develop")).unwrap();
+    let mut lines = summaries(found);
+    lines.sort();
+    assert_eq!(lines, ["[EX-2] feature", "[EX-3] develop", "[EX-4] latest"]);
+    let latest = logs(path_str, &range(Some("develop~1"), "develop")).unwrap();
+    assert_eq!(latest[0].body, "body\n\nRefs: EX-5");
+
+    assert!(logs(path_str, &range(Some("missing"), "develop")).is_err());
+    let first = logs(path_str, &range(None, "master")).unwrap();
+    assert_eq!(first, [test_commit(&root.to_string(), "[EX-1] root", "")]);
+    assert_eq!(first[0].short_sha(), &root.to_string()[..7]);
+    ::std::fs::remove_dir_all(&path).unwrap();
+}
+
+#[test]
+fn tag_ranges() {
+    let (path, repo) = test_repo("tag-ranges");
+    let path_str = path.to_str().unwrap();
+    let v1 = commit(&repo, "master", "[EX-1] first", &[]);
+    let v2 = commit(&repo, "master", "[EX-2] second", &[v1]);
+    let v3 = commit(&repo, "master", "[EX-3] third", &[v2]);
+    let tag = |name: &str, oid: Oid| {
+        let object = repo.find_object(oid, None).unwrap();
+        repo.tag_lightweight(name, &object, false).unwrap();
+    };
+    tag("v1.0", v1);
+    tag("v2.0", v2);
+    tag("other", v2);
+    tag("v3.0", v3);
+    let tree = repo.find_commit(v2).unwrap().tree_id();
+    tag("v2.5", tree);
+
+    assert_eq!(previous_tag(path_str, "v3.0", "v*").unwrap(),
+        Some(String::from("v2.0")));
+    assert_eq!(previous_tag(path_str, "v2.0", "v*").unwrap(),
+        Some(String::from("v1.0")));
+    assert_eq!(previous_tag(path_str, "v1.0", "v*").unwrap(), None);
+    assert_eq!(previous_tag(path_str, "master", "x*").unwrap(), None);
+    // v2.5 is a tag on a tree, which isn't a release.
+    assert_eq!(previous_tag(path_str, "master", "v2.5").unwrap(), None);
+
+    assert_eq!(summaries(logs(path_str, &range(Some("v1.0"), "v3.0")).unwrap()),
+        ["[EX-3] third", "[EX-2] second"]);
+    assert_eq!(commit_date(path_str, "v3.0").unwrap(), "2016-07-02");
+
+    let picked = |from, previous_tag, all_commits| {
+        pick_range(path_str, "v2.0", from, previous_tag, "master",
+            all_commits)
+    };
+    assert_eq!(picked(Some("v1.0"), Some("x*"), false).unwrap(),
+        range(Some("v1.0"), "v2.0"));
+    assert_eq!(picked(None, Some("v*"), false).unwrap(),
+        range(Some("v1.0"), "v2.0"));
+    assert_eq!(picked(None, None, false).unwrap(),
+        range(Some("master"), "v2.0"));
+    assert_eq!(picked(None, None, true).unwrap(), range(None, "v2.0"));
+    assert_eq!(picked(None, Some("x*"), true).unwrap(), range(None, "v2.0"));
+    assert_eq!(picked(None, Some("x*"), false).unwrap_err().exit_code(), 2);
+    ::std::fs::remove_dir_all(&path).unwrap();
+}
@@ -0,0 +1,982 @@
+//! Talks to the Jira REST API. Every request made by a release goes through
+//! the `JiraApi` trait, implemented by `JiraClient` which knows the root url
+//! of Jira and how to authenticate.
+
+use error::Error;
+use hyper;
+use hyper::{Client, Url};
+use hyper::client::IntoUrl;
+use hyper::client::response::Response;
+use hyper::header::{Authorization, Basic, Bearer, ContentType, Headers};
+use hyper::method::Method;
+use hyper::mime;
+use hyper::status::{StatusCode, StatusClass};
+use rustc_serialize::Decodable;
+use rustc_serialize::json::{self, Json};
+use std::cmp;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::io::Read;
+use std::thread;
+use std::time::Duration;
+use time;
+
+/// How requests are authenticated with Jira. The secrets are hidden when it
+/// is printed with `{:?}`, so that it can be logged.
+#[derive(Clone, PartialEq)]
+pub enum Credentials {
+    /// Username and password, sent with basic auth.
+    Basic { username: String, password: String },
+    /// Username and an API token from Jira Cloud, sent with basic auth.
+    Token { username: String, token: String },
+    /// Personal access token from Jira Data Center, sent as a bearer token.
+    Bearer { token: String }
+}
+
+impl fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let hidden = &"***";
+        match *self {
+            Credentials::Basic { ref username, .. } => f.debug_struct("Basic")
+                .field("username", username)
+                .field("password", hidden)
+                .finish(),
+            Credentials::Token { ref username, .. } => f.debug_struct("Token")
+                .field("username", username)
+                .field("token", hidden)
+                .finish(),
+            Credentials::Bearer { .. } => f.debug_struct("Bearer")
+                .field("token", hidden)
+                .finish()
+        }
+    }
+}
+
+#[derive(RustcDecodable, RustcEncodable, Clone, Debug, PartialEq)]
+pub struct JiraVersion {
+    pub name: String,
+    pub id: String
+}
+
+#[derive(RustcDecodable, Clone, Debug)]
+pub struct JiraNamed {
+    pub name: String
+}
+
+/// The fields of an issue used by a release.
+#[derive(RustcDecodable, Clone, Debug)]
+pub struct JiraIssueFields {
+    pub fixVersions: Vec<JiraVersion>,
+    pub summary: String,
+    pub issuetype: JiraNamed,
+    pub status: JiraNamed,
+    pub priority: Option<JiraNamed>
+}
+
+#[derive(RustcDecodable)]
+struct JiraIssue {
+    pub key: String,
+    pub fields: JiraIssueFields
+}
+
+#[derive(RustcDecodable)]
+struct JiraSearch {
+    pub issues: Vec<JiraIssue>
+}
+
+// The fields of JiraIssueFields, the only ones fetched by a search.
+const ISSUE_FIELDS: &'static str = "fixVersions,summary,issuetype,status,\
+    priority";
+
+/// How many issues are fetched by a single search. This keeps the url and
+/// the JQL query under the limits of Jira.
+pub const SEARCH_CHUNK: usize = 50;
+
+/// A workflow transition available on an issue.
+#[derive(RustcDecodable, Clone, Debug)]
+pub struct JiraTransition {
+    pub id: String,
+    pub name: String,
+    /// The status the issue is in after the transition.
+    pub to: JiraNamed
+}
+
+#[derive(RustcDecodable)]
+struct JiraTransitions {
+    pub transitions: Vec<JiraTransition>
+}
+
+// A page of the versions of a project. nextPage is the url of the next one.
+#[derive(RustcDecodable)]
+#[allow(non_snake_case)]
+struct JiraVersionPage {
+    pub values: Vec<JiraVersion>,
+    pub isLast: bool,
+    pub nextPage: Option<String>
+}
+
+#[derive(RustcDecodable)]
+#[allow(non_snake_case)]
+struct JiraVersionCounts {
+    pub issuesFixedCount: u64,
+    pub issuesAffectedCount: u64
+}
+
+// Reads the whole body of the response and decodes it from JSON.
+fn decode_response<T: Decodable>(res: &mut Response) -> Result<T, Error> {
+    let mut body = String::new();
+    try!(
+        res.read_to_string(&mut body)
+            .map_err(|e| Error::Decode(format!("Could not read response \
+                from {}", res.url), Box::new(e)))
+    );
+    json::decode(&body)
+        .map_err(|e| Error::Decode(format!("Could not decode response \
+            from {}", res.url), Box::new(e)))
+}
+
+fn object(key: &str, value: Json) -> Json {
+    let mut map = BTreeMap::new();
+    map.insert(key.to_owned(), value);
+    Json::Object(map)
+}
+
+// The body of the request adding or removing a single fix version, e.g.
+// {"update":{"fixVersions":[{"add":{"id":"10000"}}]}}.
+fn fix_version_update(operation: &str, version_id: &str) -> String {
+    let change = object(operation,
+        object("id", Json::String(version_id.to_owned())));
+    object("update", object("fixVersions", Json::Array(vec![change])))
+        .to_string()
+}
+
+fn issue_error<E>(status: StatusCode, issue_token: &str)
+        -> Result<E, Error> {
+    Err(Error::from_status(format!("Error with issue {}", issue_token), status))
+}
+
+// Splits the issues found by a search into the fields of those returned with
+// one of the keys searched, and the keys of the others. Jira finds an issue
+// which was moved to another project by its old key, but returns its new key.
+fn split_moved(issue_tokens: &[String], issues: Vec<JiraIssue>)
+        -> (HashMap<String, JiraIssueFields>, Vec<String>) {
+    let mut found = HashMap::new();
+    let mut moved = Vec::new();
+    for issue in issues {
+        if issue_tokens.contains(&issue.key) {
+            found.insert(issue.key, issue.fields);
+        } else {
+            moved.push(issue.key);
+        }
+    }
+    (found, moved)
+}
+
+fn issue_connection_error(e: hyper::Error) -> Error {
+    Error::connection("Error connecting to server".to_owned(), e)
+}
+
+/// How requests which failed for a transient reason are retried: when Jira
+/// can't be reached or answers with 429 or a 5xx status. The delay before a
+/// retry doubles on every attempt, with some jitter, up to `max_delay_ms`. A
+/// `Retry-After` header in the response is used as the delay instead, also
+/// up to `max_delay_ms`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetryPolicy {
+    /// How many times a request is retried before giving up.
+    pub retries: u32,
+    /// The delay before the first retry.
+    pub base_delay_ms: u64,
+    pub max_delay_ms: u64
+}
+
+impl RetryPolicy {
+    /// Three retries, starting after half a second.
+    pub fn new() -> RetryPolicy {
+        RetryPolicy {
+            retries: 3,
+            base_delay_ms: 500,
+            max_delay_ms: 30000
+        }
+    }
+
+    /// Never retries.
+    pub fn none() -> RetryPolicy {
+        RetryPolicy { retries: 0, ..RetryPolicy::new() }
+    }
+
+    // The delay before the retry following the attempt, counted from 0. The
+    // jitter, between 0 and 1, picks a delay between half and all of the
+    // exponential backoff so that concurrent requests don't retry together.
+    fn backoff(&self, attempt: u32, jitter: f64) -> Duration {
+        let factor = 1u64.checked_shl(attempt).unwrap_or(u64::max_value());
+        let delay = cmp::min(self.base_delay_ms.saturating_mul(factor),
+            self.max_delay_ms);
+        let half = delay / 2;
+        Duration::from_millis(half + (half as f64 * jitter) as u64)
+    }
+
+    // The delay asked for by Jira, no longer than max_delay_ms so that a
+    // wrong Retry-After doesn't hang the release.
+    fn capped(&self, delay: Duration) -> Duration {
+        cmp::min(delay, Duration::from_millis(self.max_delay_ms))
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> RetryPolicy {
+        RetryPolicy::new()
+    }
+}
+
+/// Picks the version named `name` among versions of the project. Jira doesn't
+/// allow two versions of a project whose names only differ in case, so such a
+/// version is an error: the version asked for couldn't be created either.
+pub fn pick_version<I>(project_id: &str, name: &str, versions: I)
+        -> Result<Option<JiraVersion>, Error>
+        where I: IntoIterator<Item=JiraVersion> {
+    let lower = name.to_lowercase();
+    let mut other_case = None;
+    for version in versions {
+        if version.name == name {
+            return Ok(Some(version));
+        }
+        if version.name.to_lowercase() == lower {
+            other_case = Some(version);
+        }
+    }
+    match other_case {
+        Some(version) => Err(Error::Config(format!("Project {} has a version \
+            {} which only differs from {} in case, use its exact name",
+            project_id, version.name, name))),
+        None => Ok(None)
+    }
+}
+
+// Looks for the version page by page, starting with the one at `url`, until
+// it is found or the last page is reached. `fetch` gets the page at a url.
+fn find_in_pages<F>(project_id: &str, name: &str, url: String, mut fetch: F)
+        -> Result<Option<JiraVersion>, Error>
+        where F: FnMut(&str) -> Result<JiraVersionPage, Error> {
+    let mut next = Some(url);
+    while let Some(url) = next {
+        let page = try!(fetch(&url));
+        let found = try!(pick_version(project_id, name, page.values));
+        if found.is_some() {
+            return Ok(found);
+        }
+        next = if page.isLast { None } else { page.nextPage };
+    }
+    Ok(None)
+}
+
+// A number between 0 and 1 which is good enough to spread retries.
+fn jitter() -> f64 {
+    (time::precise_time_ns() % 1000) as f64 / 1000.0
+}
+
+// Whether a request can be retried after failing with the status, None
+// meaning that Jira could not be reached. POST requests aren't idempotent:
+// they are only retried on 429, when Jira refused to process them.
+fn retryable(method: &Method, status: Option<StatusCode>) -> bool {
+    match status {
+        Some(StatusCode::TooManyRequests) => true,
+        _ if *method == Method::Post => false,
+        None => true,
+        Some(status) => status.class() == StatusClass::ServerError
+    }
+}
+
+// The delay asked for by the Retry-After header, given either in seconds or
+// as an HTTP date.
+fn retry_after(headers: &Headers) -> Option<Duration> {
+    let raw = match headers.get_raw("Retry-After") {
+        Some(raw) if !raw.is_empty() => raw,
+        _ => return None
+    };
+    let value = String::from_utf8_lossy(&raw[0]);
+    let value = value.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    time::strptime(value, "%a, %d %b %Y %H:%M:%S GMT")
+        .ok()
+        .map(|date| {
+            let seconds = (date.to_timespec() - time::get_time())
+                .num_seconds();
+            Duration::from_secs(cmp::max(seconds, 0) as u64)
+        })
+}
+
+/// The operations on Jira needed by a release. `JiraClient` sends them to the
+/// REST API of a Jira instance, while `fake::FakeJira` keeps everything in
+/// memory for tests. Implementations are shared between the threads updating
+/// the issues.
+pub trait JiraApi: Sync {
+    /// Creates the version in the project.
+    fn create_version(&self, project_id: &str, name: &str)
+        -> Result<JiraVersion, Error>;
+
+    /// Returns the version of the project with this name, or None if it
+    /// doesn't exist. See `pick_version` for names differing in case.
+    fn find_version(&self, project_id: &str, name: &str)
+        -> Result<Option<JiraVersion>, Error>;
+
+    /// Marks the version as released on the date, given as YYYY-MM-DD.
+    fn release_version(
+        &self,
+        version: &JiraVersion,
+        release_date: &str,
+        description: Option<&str>
+        ) -> Result<(), Error>;
+
+    fn unrelease_version(&self, version: &JiraVersion) -> Result<(), Error>;
+
+    /// How many issues have the version as a fix or affected version.
+    fn version_issue_count(&self, version: &JiraVersion)
+        -> Result<u64, Error>;
+
+    fn delete_version(&self, version: &JiraVersion) -> Result<(), Error>;
+
+    /// The issues with these keys, by the key asked for even when the issue
+    /// was moved and has a new one. The issues which don't exist are left
+    /// out. `ReleasePlan::publish` searches `SEARCH_CHUNK` keys at a
+    /// time, so that a failed search only fails the issues it included.
+    fn search_issues(&self, issue_tokens: &[String])
+        -> Result<HashMap<String, JiraIssueFields>, Error>;
+
+    /// Adds the fix version to the issue, leaving its other fix versions
+    /// as they are.
+    fn add_issue_version(&self, issue_token: &str, version: &JiraVersion)
+        -> Result<(), Error>;
+
+    /// Removes the fix version from the issue. Returns false if the issue
+    /// doesn't exist anymore.
+    fn remove_issue_version(&self, issue_token: &str, version_id: &str)
+        -> Result<bool, Error>;
+
+    /// The transitions which can be done from the current status of the
+    /// issue.
+    fn transitions(&self, issue_token: &str)
+        -> Result<Vec<JiraTransition>, Error>;
+
+    fn transition(&self, issue_token: &str, transition: &JiraTransition)
+        -> Result<(), Error>;
+
+    /// Adds a comment to the issue, `body` being in Jira's wiki markup.
+    fn comment(&self, issue_token: &str, body: &str) -> Result<(), Error>;
+}
+
+/// Sends the requests to the REST API of a Jira instance through hyper.
+pub struct JiraClient {
+    client: Client,
+    url: String,
+    credentials: Credentials,
+    retry: RetryPolicy
+}
+
+impl JiraClient {
+    /// `url` is the root url of Jira, e.g. `https://jira.example.com`.
+    /// Failed requests are retried with `RetryPolicy::new()`.
+    pub fn new(url: &str, credentials: Credentials) -> JiraClient {
+        JiraClient {
+            client: Client::new(),
+            url: url.to_owned(),
+            credentials: credentials,
+            retry: RetryPolicy::new()
+        }
+    }
+
+    pub fn with_retry(self, retry: RetryPolicy) -> JiraClient {
+        JiraClient { retry: retry, ..self }
+    }
+
+    /// The root url of Jira, as given to `new`.
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    fn api_url(&self, path: &str) -> String {
+        self.url.clone() + "/rest/api/2/" + path
+    }
+
+    // The api url with the query string, whose values are encoded.
+    fn api_query(&self, path: &str, query: &[(&str, &str)])
+            -> Result<String, Error> {
+        let mut url = try!(
+            Url::parse(&self.api_url(path))
+                .map_err(|e| Error::Config(format!("Invalid Jira URL {}: {}",
+                    self.url, e)))
+        );
+        url.query_pairs_mut().extend_pairs(query);
+        Ok(url.to_string())
+    }
+
+    // Adds or removes a single fix version of the issue with an update
+    // operation, so that changes made to the others in the meantime are
+    // kept.
+    fn update_fix_versions(&self, issue_token: &str, operation: &str,
+            version_id: &str) -> Result<Response, Error> {
+        let url = self.api_url(&(String::from("issue/") + issue_token));
+        let payload = fix_version_update(operation, version_id);
+        debug!("PUT {} to url: {}", payload, url);
+        self.send(Method::Put, &url, Some(&payload))
+            .map_err(issue_connection_error)
+    }
+
+    // The url searching the issues with these keys, with the fields needed.
+    fn search_url(&self, issue_tokens: &[String]) -> Result<String, Error> {
+        let keys: Vec<String> = issue_tokens
+            .iter()
+            .map(|key| format!("\"{}\"", key))
+            .collect();
+        let jql = format!("key in ({})", keys.join(","));
+        let max_results = issue_tokens.len().to_string();
+        // Keys which don't exist are only a warning instead of failing the
+        // whole search.
+        self.api_query("search", &[
+            ("jql", &jql),
+            ("fields", ISSUE_FIELDS),
+            ("maxResults", &max_results),
+            ("validateQuery", "warn")
+        ])
+    }
+
+    // Fetches the issues with a single search.
+    fn search_chunk(&self, issue_tokens: &[String])
+            -> Result<Vec<JiraIssue>, Error> {
+        let url = try!(self.search_url(issue_tokens));
+        debug!("searching {} issues through url: {}", issue_tokens.len(),
+            url);
+        let mut res = try!(
+            self.send(Method::Get, &url, None)
+                .map_err(issue_connection_error)
+        );
+        match res.status {
+            StatusCode::Ok => {
+                let search: JiraSearch = try!(decode_response(&mut res));
+                Ok(search.issues)
+            },
+            rest => Err(Error::from_status(String::from("Server error \
+                searching issues"), rest))
+        }
+    }
+
+    // Fetches a single issue, whose key can be an old one: the issue is then
+    // returned with its new key. None when the issue doesn't exist.
+    fn fetch_issue(&self, issue_token: &str)
+            -> Result<Option<JiraIssue>, Error> {
+        let url = try!(self.api_query(&format!("issue/{}", issue_token),
+            &[("fields", ISSUE_FIELDS)]));
+        debug!("fetching issue {} through url: {}", issue_token, url);
+        let mut res = try!(
+            self.send(Method::Get, &url, None)
+                .map_err(issue_connection_error)
+        );
+        match res.status {
+            StatusCode::Ok => Ok(Some(try!(decode_response(&mut res)))),
+            StatusCode::NotFound => Ok(None),
+            rest => issue_error(rest, issue_token)
+        }
+    }
+
+    // Sends the request, retrying it as long as the retry policy allows.
+    // The last response or error is returned.
+    fn send(&self, method: Method, url: &str, payload: Option<&str>)
+            -> Result<Response, hyper::Error> {
+        let mut attempt = 0;
+        loop {
+            let result = self.send_once(method.clone(), url, payload);
+            if attempt >= self.retry.retries {
+                return result;
+            }
+            let delay = match result {
+                Err(ref e) if retryable(&method, None) => {
+                    warn!("{} {} failed: {}", method, url, e);
+                    self.retry.backoff(attempt, jitter())
+                },
+                Ok(ref res) if retryable(&method, Some(res.status)) => {
+                    warn!("{} {} failed: {}", method, url, res.status);
+                    retry_after(&res.headers)
+                        .map(|delay| self.retry.capped(delay))
+                        .unwrap_or_else(|| {
+                            self.retry.backoff(attempt, jitter())
+                        })
+                },
+                _ => return result
+            };
+            let millis = delay.as_secs() * 1000 +
+                (delay.subsec_nanos() / 1000000) as u64;
+            warn!("retrying in {} ms", millis);
+            thread::sleep(delay);
+            attempt += 1;
+        }
+    }
+
+    fn send_once<U: IntoUrl>(&self, method: Method, url: U,
+            payload: Option<&str>) -> Result<Response, hyper::Error> {
+        let mut req = self.client.request(method, url);
+        if let Some(payload_str) = payload {
+            let content_type = ContentType(
+                mime::Mime(
+                    mime::TopLevel::Application, mime::SubLevel::Json,
+                    Vec::new()
+                )
+            );
+            req = req
+                .header(content_type)
+                .body(payload_str);
+        }
+        match self.credentials {
+            Credentials::Basic { ref username, ref password } |
+            Credentials::Token { ref username, token: ref password } => {
+                req.header(
+                    Authorization(
+                        Basic {
+                            username: username.clone(),
+                            password: Some(password.clone())
+                        }
+                    )
+                ).send()
+            },
+            Credentials::Bearer { ref token } => {
+                req.header(Authorization(Bearer { token: token.clone() }))
+                    .send()
+            }
+        }
+    }
+
+    // Sends a request about a version, returning the response when
+    // successful.
+    fn version_request(
+            &self,
+            method: Method,
+            path: &str,
+            payload: Option<&str>,
+            what: &str
+            ) -> Result<Response, Error> {
+        let url = self.api_url(&(String::from("version/") + path));
+        debug!("{} through url: {}", what, url);
+        let res = try!(
+            self.send(method, &url, payload)
+                .map_err(|e| Error::connection(format!("Could not request {}",
+                    what), e))
+        );
+        match res.status.class() {
+            StatusClass::Success => Ok(res),
+            _ => Err(Error::from_status(format!("Server error {}", what),
+                res.status))
+        }
+    }
+}
+
+impl JiraApi for JiraClient {
+    fn create_version(&self, project_id: &str, name: &str)
+            -> Result<JiraVersion, Error> {
+        let mut map = BTreeMap::new();
+        map.insert("name".to_owned(), Json::String(name.to_owned()));
+        map.insert("project".to_owned(), Json::String(project_id.to_owned()));
+        let payload_obj = Json::Object(map).to_string();
+
+        let url = self.api_url("version");
+        debug!("creating Jira version {} through url: {}", name, url);
+        debug!("POST payload: {:?}", payload_obj);
+        let mut res = try!(
+            self.send(Method::Post, &url, Some(&payload_obj))
+                .map_err(|e| Error::connection("Could not request creation \
+                    of Jira version".to_owned(), e))
+        );
+
+        match res.status.class() {
+            StatusClass::Success => decode_response(&mut res),
+            _ => {
+                let msg = format!("Server error creating Jira version {} in \
+                    project {}", name, project_id);
+                Err(Error::from_status(msg, res.status))
+            }
+        }
+    }
+
+    // Only fetches the versions whose name contains the one looked for,
+    // page by page until it is found.
+    fn find_version(&self, project_id: &str, name: &str)
+            -> Result<Option<JiraVersion>, Error> {
+        let url = try!(self.api_query(
+            &format!("project/{}/version", project_id), &[("query", name)]
+        ));
+        find_in_pages(project_id, name, url, |url| {
+            debug!("fetching jira versions for project {} with url: {}",
+                project_id, url);
+            let mut res = try!(
+                self.send(Method::Get, url, None)
+                    .map_err(|e| Error::connection("Could not requests \
+                        versions available for project".to_owned(), e))
+            );
+            if res.status != StatusCode::Ok {
+                let msg = format!("Server error fetching Jira versions \
+                    for project {}", project_id);
+                return Err(Error::from_status(msg, res.status));
+            }
+            decode_response(&mut res)
+        })
+    }
+
+    fn release_version(
+            &self,
+            version: &JiraVersion,
+            release_date: &str,
+            description: Option<&str>
+            ) -> Result<(), Error> {
+        let mut map = BTreeMap::new();
+        map.insert("released".to_owned(), Json::Boolean(true));
+        map.insert("releaseDate".to_owned(),
+            Json::String(release_date.to_owned()));
+        if let Some(description) = description {
+            map.insert("description".to_owned(),
+                Json::String(description.to_owned()));
+        }
+        let payload = Json::Object(map).to_string();
+        let what = format!("releasing Jira version {}", version.name);
+        self.version_request(Method::Put, &version.id, Some(&payload), &what)
+            .map(|_| ())
+    }
+
+    fn unrelease_version(&self, version: &JiraVersion)
+            -> Result<(), Error> {
+        let payload = object("released", Json::Boolean(false)).to_string();
+        let what = format!("unreleasing Jira version {}", version.name);
+        self.version_request(Method::Put, &version.id, Some(&payload), &what)
+            .map(|_| ())
+    }
+
+    fn version_issue_count(&self, version: &JiraVersion)
+            -> Result<u64, Error> {
+        let what = format!("counting issues of Jira version {}", version.name);
+        let mut res = try!(self.version_request(Method::Get,
+            &(version.id.clone() + "/relatedIssueCounts"), None, &what));
+        let counts: JiraVersionCounts = try!(decode_response(&mut res));
+        Ok(counts.issuesFixedCount + counts.issuesAffectedCount)
+    }
+
+    fn delete_version(&self, version: &JiraVersion) -> Result<(), Error> {
+        let what = format!("deleting Jira version {}", version.name);
+        self.version_request(Method::Delete, &version.id, None, &what)
+            .map(|_| ())
+    }
+
+    fn search_issues(&self, issue_tokens: &[String])
+            -> Result<HashMap<String, JiraIssueFields>, Error> {
+        let mut found = HashMap::new();
+        for chunk in issue_tokens.chunks(SEARCH_CHUNK) {
+            let (fields, moved) =
+                split_moved(chunk, try!(self.search_chunk(chunk)));
+            found.extend(fields);
+            if moved.is_empty() {
+                continue;
+            }
+            // The moved issues are among the keys missing, which are fetched
+            // one by one to know which is which. They keep the key they were
+            // searched with.
+            let missing: Vec<String> = chunk
+                .iter()
+                .filter(|key| !found.contains_key(*key))
+                .cloned()
+                .collect();
+            for key in missing {
+                if let Some(issue) = try!(self.fetch_issue(&key)) {
+                    warn!("Issue {} was moved to {}", key, issue.key);
+                    found.insert(key, issue.fields);
+                }
+            }
+        }
+        Ok(found)
+    }
+
+    fn add_issue_version(&self, issue_token: &str, version: &JiraVersion)
+            -> Result<(), Error> {
+        debug!("adding version {} to issue {}", version.name, issue_token);
+        let res = try!(self.update_fix_versions(issue_token, "add",
+            &version.id));
+        match res.status.class() {
+            StatusClass::Success => Ok(()),
+            _ => issue_error(res.status, issue_token)
+        }
+    }
+
+    fn remove_issue_version(&self, issue_token: &str, version_id: &str)
+            -> Result<bool, Error> {
+        debug!("removing version {} from issue {}", version_id, issue_token);
+        let res = try!(self.update_fix_versions(issue_token, "remove",
+            version_id));
+        match res.status {
+            StatusCode::NotFound => Ok(false),
+            status if status.class() == StatusClass::Success => Ok(true),
+            rest => issue_error(rest, issue_token)
+        }
+    }
+
+    fn transitions(&self, issue_token: &str)
+            -> Result<Vec<JiraTransition>, Error> {
+        let url = self.api_url(&format!("issue/{}/transitions", issue_token));
+        debug!("fetching transitions of {} through url: {}", issue_token,
+            url);
+        let mut res = try!(
+            self.send(Method::Get, &url, None)
+                .map_err(issue_connection_error)
+        );
+        match res.status {
+            StatusCode::Ok => {
+                let transitions: JiraTransitions =
+                    try!(decode_response(&mut res));
+                Ok(transitions.transitions)
+            },
+            rest => issue_error(rest, issue_token)
+        }
+    }
+
+    fn transition(&self, issue_token: &str, transition: &JiraTransition)
+            -> Result<(), Error> {
+        let url = self.api_url(&format!("issue/{}/transitions", issue_token));
+        debug!("transitioning {} with {} through url: {}", issue_token,
+            transition.name, url);
+        let payload = object("transition",
+            object("id", Json::String(transition.id.clone()))).to_string();
+        let res = try!(
+            self.send(Method::Post, &url, Some(&payload))
+                .map_err(issue_connection_error)
+        );
+        match res.status.class() {
+            StatusClass::Success => Ok(()),
+            _ => issue_error(res.status, issue_token)
+        }
+    }
+
+    fn comment(&self, issue_token: &str, body: &str)
+            -> Result<(), Error> {
+        let url = self.api_url(&format!("issue/{}/comment", issue_token));
+        debug!("commenting on {} through url: {}", issue_token, url);
+        let payload = object("body", Json::String(body.to_owned())).to_string();
+        let res = try!(
+            self.send(Method::Post, &url, Some(&payload))
+                .map_err(issue_connection_error)
+        );
+        match res.status.class() {
+            StatusClass::Success => Ok(()),
+            _ => issue_error(res.status, issue_token)
+        }
+    }
+}
+
+#[test]
+fn fixtures_decoded() {
+    let issue: JiraIssue =
+        json::decode(include_str!("../test-fixtures/issue1_response.json"))
+            .unwrap();
+    assert_eq!(issue.fields.issuetype.name, "Story");
+    assert_eq!(issue.fields.status.name, "To Do");
+    assert_eq!(issue.fields.fixVersions[0].name, "Version 2.0");
+    let versions: Vec<JiraVersion> =
+        json::decode(include_str!("../test-fixtures/versions_response.json"))
+            .unwrap();
+    assert_eq!(versions[0], JiraVersion {
+        name: String::from("Version 1.0"),
+        id: String::from("10000")
+    });
+    let created: JiraVersion =
+        json::decode(include_str!("../test-fixtures/create_version.json"))
+            .unwrap();
+    assert_eq!(created.id, "10007");
+}
+
+#[test]
+fn retry_delays() {
+    let retry = RetryPolicy::new();
+    assert_eq!(retry.backoff(0, 0.0), Duration::from_millis(250));
+    assert_eq!(retry.backoff(0, 1.0), Duration::from_millis(500));
+    assert_eq!(retry.backoff(2, 1.0), Duration::from_millis(2000));
+    assert_eq!(retry.backoff(40, 1.0), Duration::from_millis(30000));
+    assert_eq!(retry.backoff(80, 0.0), Duration::from_millis(15000));
+
+    assert!(retryable(&Method::Get, None));
+    assert!(retryable(&Method::Put, Some(StatusCode::BadGateway)));
+    assert!(retryable(&Method::Post, Some(StatusCode::TooManyRequests)));
+    assert!(!retryable(&Method::Post, None));
+    assert!(!retryable(&Method::Post, Some(StatusCode::ServiceUnavailable)));
+    assert!(!retryable(&Method::Get, Some(StatusCode::NotFound)));
+
+    let mut headers = Headers::new();
+    assert_eq!(retry_after(&headers), None);
+    headers.set_raw("Retry-After", vec![b"120".to_vec()]);
+    assert_eq!(retry_after(&headers), Some(Duration::from_secs(120)));
+    headers.set_raw("Retry-After",
+        vec![b"Wed, 21 Oct 2015 07:28:00 GMT".to_vec()]);
+    assert_eq!(retry_after(&headers), Some(Duration::from_secs(0)));
+
+    assert_eq!(retry.capped(Duration::from_secs(120)),
+        Duration::from_millis(30000));
+    assert_eq!(retry.capped(Duration::from_secs(2)), Duration::from_secs(2));
+}
+
+#[test]
+fn versions_picked() {
+    let page: JiraVersionPage = json::decode(r#"{
+        "self": "http://jira/rest/api/2/project/EX/version?startAt=0",
+        "nextPage": "http://jira/rest/api/2/project/EX/version?startAt=2",
+        "maxResults": 2,
+        "startAt": 0,
+        "total": 3,
+        "isLast": false,
+        "values": [
+            {"id": "10000", "name": "1.2.0-RC1", "released": true},
+            {"id": "10001", "name": "1.2.0", "released": false}
+        ]
+    }"#).unwrap();
+    assert!(!page.isLast);
+    assert!(page.nextPage.is_some());
+
+    let picked = pick_version("EX", "1.2.0", page.values.clone()).unwrap();
+    assert_eq!(picked.unwrap().id, "10001");
+    assert!(pick_version("EX", "1.3.0", page.values.clone())
+        .unwrap().is_none());
+    let mismatch = pick_version("EX", "1.2.0-rc1", page.values).unwrap_err();
+    assert_eq!(mismatch.exit_code(), 2);
+}
+
+#[cfg(test)]
+fn version_page(names: &[&str], next: Option<&str>) -> JiraVersionPage {
+    JiraVersionPage {
+        values: names.iter().enumerate().map(|(i, name)| JiraVersion {
+            name: name.to_string(),
+            id: (10000 + i).to_string()
+        }).collect(),
+        isLast: next.is_none(),
+        nextPage: next.map(String::from)
+    }
+}
+
+#[test]
+fn version_pages() {
+    let pages = |fetched: &mut Vec<String>, url: &str| {
+        fetched.push(url.to_owned());
+        match url {
+            "p1" => Ok(version_page(&["1.0.0", "1.1.0"], Some("p2"))),
+            "p2" => Ok(version_page(&["1.2.0"], Some("p3"))),
+            "p3" => Ok(version_page(&["1.3.0"], None)),
+            _ => Err(Error::Config(String::from("No such page")))
+        }
+    };
+    let mut fetched = Vec::new();
+    let found = find_in_pages("EX", "1.2.0", String::from("p1"),
+        |url| pages(&mut fetched, url)).unwrap();
+    assert_eq!(found.unwrap().name, "1.2.0");
+    assert_eq!(fetched, ["p1", "p2"]);
+
+    let mut fetched = Vec::new();
+    let missing = find_in_pages("EX", "2.0.0", String::from("p1"),
+        |url| pages(&mut fetched, url)).unwrap();
+    assert!(missing.is_none());
+    assert_eq!(fetched, ["p1", "p2", "p3"]);
+
+    // The next page isn't followed once a page is the last one.
+    let mut fetched = 0;
+    let missing = find_in_pages("EX", "1.2.0", String::from("p1"), |_| {
+        fetched += 1;
+        let mut page = version_page(&["1.0.0"], Some("p2"));
+        page.isLast = true;
+        Ok(page)
+    }).unwrap();
+    assert!(missing.is_none());
+    assert_eq!(fetched, 1);
+
+    // A page which can't be fetched ends the search.
+    let broken = find_in_pages("EX", "2.0.0", String::from("p0"), |url| {
+        match url {
+            "p0" => Ok(version_page(&["0.9.0"], Some("p9"))),
+            url => pages(&mut Vec::new(), url)
+        }
+    });
+    assert_eq!(broken.unwrap_err().exit_code(), 2);
+}
+
+#[test]
+fn credentials_hidden() {
+    let basic = Credentials::Basic {
+        username: String::from("me"),
+        password: String::from("secret")
+    };
+    assert_eq!(format!("{:?}", basic),
+        r#"Basic { username: "me", password: "***" }"#);
+    let token = Credentials::Token {
+        username: String::from("me"),
+        token: String::from("secret")
+    };
+    assert_eq!(format!("{:?}", token),
+        r#"Token { username: "me", token: "***" }"#);
+    let bearer = Credentials::Bearer { token: String::from("secret") };
+    assert_eq!(format!("{:?}", bearer), r#"Bearer { token: "***" }"#);
+}
+
+#[test]
+fn fix_version_updates() {
+    assert_eq!(fix_version_update("add", "10000"),
+        r#"{"update":{"fixVersions":[{"add":{"id":"10000"}}]}}"#);
+    assert_eq!(fix_version_update("remove", "10001"),
+        r#"{"update":{"fixVersions":[{"remove":{"id":"10001"}}]}}"#);
+}
+
+#[test]
+fn search_urls() {
+    let client = JiraClient::new("http://jira", Credentials::Basic {
+        username: String::from("me"),
+        password: String::from("secret")
+    });
+    let keys = [String::from("EX-1"), String::from("EX-2")];
+    assert_eq!(client.search_url(&keys).unwrap(),
+        String::from("http://jira/rest/api/2/search") +
+        "?jql=key+in+%28%22EX-1%22%2C%22EX-2%22%29" +
+        "&fields=fixVersions%2Csummary%2Cissuetype%2Cstatus%2Cpriority" +
+        "&maxResults=2&validateQuery=warn");
+    let invalid = JiraClient::new("jira", Credentials::Basic {
+        username: String::from("me"),
+        password: String::from("secret")
+    });
+    assert_eq!(invalid.search_url(&keys).unwrap_err().exit_code(), 2);
+}
+
+#[test]
+fn search_decoded() {
+    let search: JiraSearch = json::decode(r#"{
+        "expand": "names,schema",
+        "startAt": 0,
+        "maxResults": 2,
+        "total": 1,
+        "issues": [{
+            "id": "10001",
+            "key": "EX-1",
+            "fields": {
+                "fixVersions": [{"id": "10000", "name": "1.1.0"}],
+                "summary": "First",
+                "issuetype": {"name": "Bug"},
+                "status": {"name": "Done"},
+                "priority": null
+            }
+        }],
+        "warningMessages": ["The issue key 'EX-2' does not exist."]
+    }"#).unwrap();
+    assert_eq!(search.issues.len(), 1);
+    assert_eq!(search.issues[0].key, "EX-1");
+    assert_eq!(search.issues[0].fields.fixVersions[0].name, "1.1.0");
+    assert!(search.issues[0].fields.priority.is_none());
+
+    let keys = [String::from("EX-1"), String::from("OLD-1")];
+    let moved = JiraIssue {
+        key: String::from("NEW-1"),
+        fields: search.issues[0].fields.clone()
+    };
+    let (found, moved) = split_moved(&keys, vec![moved]);
+    assert!(found.is_empty());
+    assert_eq!(moved, ["NEW-1"]);
+    let (found, moved) = split_moved(&keys, search.issues);
+    assert_eq!(found.keys().collect::<Vec<_>>(), ["EX-1"]);
+    assert!(moved.is_empty());
+}
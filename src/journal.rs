@@ -0,0 +1,320 @@
+//! Records every change made to Jira during a run, one JSON object per line,
+//! so that the run can be rolled back. An entry is written as soon as the
+//! change is made, which keeps the journal complete when a run fails halfway.
+//! Runs are appended to the file, every entry tagged with the run which
+//! recorded it, and a rollback only undoes the last run unless asked to undo
+//! them all.
+
+use error::Error;
+use rustc_serialize::json;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::process;
+use std::sync::Mutex;
+use jira::{JiraApi, JiraVersion};
+use time;
+
+#[derive(RustcEncodable, RustcDecodable, Debug, Clone, PartialEq)]
+pub enum Action {
+    CreateVersion,
+    ReleaseVersion,
+    AddFixVersion,
+    Transition,
+    Comment
+}
+
+#[derive(RustcEncodable, RustcDecodable, Debug, Clone, PartialEq)]
+pub struct Entry {
+    pub action: Action,
+    pub version_id: String,
+    pub version_name: String,
+    /// Set for the changes made to a version.
+    pub project_id: Option<String>,
+    /// Set for the changes made to an issue.
+    pub issue: Option<String>,
+    /// The run which made the change, set when the entry is recorded. None
+    /// in journals written before runs were tagged.
+    pub run: Option<String>
+}
+
+impl Entry {
+    pub fn project(action: Action, project_id: &str, version: &JiraVersion)
+            -> Entry {
+        Entry {
+            action: action,
+            version_id: version.id.clone(),
+            version_name: version.name.clone(),
+            project_id: Some(project_id.to_owned()),
+            issue: None,
+            run: None
+        }
+    }
+
+    pub fn issue(action: Action, issue: &str, version: &JiraVersion)
+            -> Entry {
+        Entry {
+            action: action,
+            version_id: version.id.clone(),
+            version_name: version.name.clone(),
+            project_id: None,
+            issue: Some(issue.to_owned()),
+            run: None
+        }
+    }
+
+    pub fn version(&self) -> JiraVersion {
+        JiraVersion {
+            name: self.version_name.clone(),
+            id: self.version_id.clone()
+        }
+    }
+}
+
+/// Shared by the threads updating the issues. A disabled journal records
+/// nothing.
+pub struct Journal {
+    file: Option<Mutex<File>>,
+    run: String
+}
+
+// Tells the runs appended to the same journal apart, e.g.
+// "1467417600.123456789-4242".
+fn run_id() -> String {
+    let now = time::get_time();
+    format!("{}.{:09}-{}", now.sec, now.nsec, process::id())
+}
+
+impl Journal {
+    pub fn disabled() -> Journal {
+        Journal { file: None, run: String::new() }
+    }
+
+    pub fn open(path: &str) -> Result<Journal, Error> {
+        let file = try!(
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .map_err(|e| Error::Io(format!("Could not open journal {}",
+                    path), e))
+        );
+        Ok(Journal { file: Some(Mutex::new(file)), run: run_id() })
+    }
+
+    /// Appends the entry, tagged with the run of this journal.
+    pub fn record(&self, mut entry: Entry) -> Result<(), Error> {
+        let file = match self.file {
+            Some(ref file) => file,
+            None => return Ok(())
+        };
+        entry.run = Some(self.run.clone());
+        let line = json::encode(&entry).unwrap() + "\n";
+        let mut file = file.lock().unwrap();
+        file.write_all(line.as_bytes())
+            .and_then(|_| file.flush())
+            .map_err(|e| Error::Io(String::from("Could not write to the \
+                journal"), e))
+    }
+}
+
+/// Reads back the entries of a journal, in the order they were recorded.
+pub fn read(path: &str) -> Result<Vec<Entry>, Error> {
+    let file = try!(
+        File::open(path)
+            .map_err(|e| Error::Io(format!("Could not open journal {}", path),
+                e))
+    );
+    let mut entries = Vec::new();
+    for (i, line) in BufReader::new(file).lines().enumerate() {
+        let line = try!(
+            line.map_err(|e| Error::Io(format!("Could not read journal {}",
+                path), e))
+        );
+        if line.trim().is_empty() {
+            continue;
+        }
+        let entry = try!(
+            json::decode(&line)
+                .map_err(|e| Error::Decode(format!("Invalid entry in journal \
+                    {} at line {}", path, i + 1), Box::new(e)))
+        );
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+/// The entries recorded by the last run appended to the journal, in the order
+/// they were recorded.
+pub fn last_run(entries: Vec<Entry>) -> Vec<Entry> {
+    let run = match entries.last() {
+        Some(entry) => entry.run.clone(),
+        None => return entries
+    };
+    entries.into_iter().filter(|entry| entry.run == run).collect()
+}
+
+/// What undoing an entry did, or would do on a dry run.
+#[derive(Debug, PartialEq)]
+pub enum Undone {
+    /// The fix version was removed from the issue.
+    FixVersionRemoved,
+    /// The issue doesn't exist anymore, so there was nothing to remove.
+    IssueMissing,
+    /// The version was marked as unreleased.
+    Unreleased,
+    /// The version was deleted. On a dry run, it would only be deleted if no
+    /// issues are left in it.
+    VersionDeleted,
+    /// The version was kept since this many issues are still in it.
+    VersionKept(u64),
+    /// Transitions and comments can't be undone.
+    NotUndone,
+    /// The entry lacks the issue or project needed to undo it.
+    Invalid
+}
+
+/// Undoes the change recorded by the entry. Entries should be undone the
+/// latest first. A version is deleted only if no issues are left in it, so
+/// that versions used by other runs or people are kept.
+pub fn undo(client: &dyn JiraApi, entry: &Entry, dry_run: bool)
+        -> Result<Undone, Error> {
+    let version = entry.version();
+    match (&entry.action, &entry.issue, &entry.project_id) {
+        (&Action::AddFixVersion, &Some(ref issue), _) => {
+            if dry_run ||
+                    try!(client.remove_issue_version(issue, &version.id)) {
+                Ok(Undone::FixVersionRemoved)
+            } else {
+                Ok(Undone::IssueMissing)
+            }
+        },
+        (&Action::ReleaseVersion, _, &Some(_)) => {
+            if !dry_run {
+                try!(client.unrelease_version(&version));
+            }
+            Ok(Undone::Unreleased)
+        },
+        (&Action::CreateVersion, _, &Some(_)) => {
+            if dry_run {
+                return Ok(Undone::VersionDeleted);
+            }
+            let left = try!(client.version_issue_count(&version));
+            if left == 0 {
+                try!(client.delete_version(&version));
+                Ok(Undone::VersionDeleted)
+            } else {
+                Ok(Undone::VersionKept(left))
+            }
+        },
+        (&Action::Transition, &Some(_), _) |
+        (&Action::Comment, &Some(_), _) => Ok(Undone::NotUndone),
+        _ => Ok(Undone::Invalid)
+    }
+}
+
+/// Undoes the entries, the latest first, and calls `undone` with each entry
+/// and what undoing it did. Stops at the first error, after the entries
+/// already undone were passed to `undone`.
+pub fn rollback<F>(client: &dyn JiraApi, entries: &[Entry], dry_run: bool,
+        mut undone: F) -> Result<(), Error>
+        where F: FnMut(&Entry, Undone) {
+    for entry in entries.iter().rev() {
+        undone(entry, try!(undo(client, entry, dry_run)));
+    }
+    Ok(())
+}
+
+#[test]
+fn written_and_read() {
+    let path = ::std::env::temp_dir().join(
+        format!("jira-releaser-journal-{}", ::std::process::id())
+    );
+    let path = path.to_str().unwrap();
+    let version = JiraVersion {
+        name: String::from("1.2.0"),
+        id: String::from("10")
+    };
+    let created = Entry::project(Action::CreateVersion, "EX", &version);
+    let added = Entry::issue(Action::AddFixVersion, "EX-1", &version);
+    {
+        let journal = Journal::open(path).unwrap();
+        journal.record(created.clone()).unwrap();
+    }
+    {
+        let journal = Journal::open(path).unwrap();
+        journal.record(added.clone()).unwrap();
+    }
+    Journal::disabled().record(added.clone()).unwrap();
+    let entries = read(path).unwrap();
+    assert_eq!(entries.len(), 2);
+    assert!(entries[0].run.is_some());
+    assert!(entries[0].run != entries[1].run);
+    assert_eq!(Entry { run: None, ..entries[0].clone() }, created);
+    assert_eq!(Entry { run: None, ..entries[1].clone() }, added);
+    assert_eq!(last_run(entries.clone()), &entries[1..]);
+    assert!(last_run(Vec::new()).is_empty());
+
+    // Entries written before runs were tagged are a single run.
+    File::create(path).unwrap().write_all(b"{\"action\": \"Comment\", \
+        \"version_id\": \"10\", \"version_name\": \"1.2.0\", \
+        \"project_id\": null, \"issue\": \"EX-1\"}\n").unwrap();
+    let untagged = read(path).unwrap();
+    assert_eq!(untagged[0].run, None);
+    assert_eq!(last_run(untagged).len(), 1);
+
+    File::create(path).unwrap().write_all(b"{\"action\": 1}\n").unwrap();
+    let invalid = read(path).unwrap_err();
+    assert_eq!(invalid.exit_code(), 7);
+    ::std::fs::remove_file(path).unwrap();
+    assert_eq!(read(path).unwrap_err().exit_code(), 8);
+}
+
+#[test]
+fn undone() {
+    let jira = ::fake::FakeJira::new();
+    jira.add_issue("EX-1", "First");
+    let version = jira.add_version("EX", "1.2.0");
+    jira.add_issue_version("EX-1", &version).unwrap();
+    jira.release_version(&version, "2024-01-01", None).unwrap();
+    let created = Entry::project(Action::CreateVersion, "EX", &version);
+    let released = Entry::project(Action::ReleaseVersion, "EX", &version);
+    let added = Entry::issue(Action::AddFixVersion, "EX-1", &version);
+    let missing = Entry::issue(Action::AddFixVersion, "EX-2", &version);
+    let commented = Entry::issue(Action::Comment, "EX-1", &version);
+
+    assert_eq!(undo(&jira, &commented, false).unwrap(), Undone::NotUndone);
+    assert_eq!(undo(&jira, &created, false).unwrap(), Undone::VersionKept(1));
+    assert_eq!(undo(&jira, &added, true).unwrap(), Undone::FixVersionRemoved);
+    assert_eq!(jira.issue("EX-1").unwrap().fixVersions.len(), 1);
+    assert_eq!(undo(&jira, &added, false).unwrap(),
+        Undone::FixVersionRemoved);
+    assert!(jira.issue("EX-1").unwrap().fixVersions.is_empty());
+    assert_eq!(undo(&jira, &missing, false).unwrap(), Undone::IssueMissing);
+    assert_eq!(undo(&jira, &released, false).unwrap(), Undone::Unreleased);
+    assert!(jira.release_date(&version).is_none());
+    assert_eq!(undo(&jira, &created, false).unwrap(), Undone::VersionDeleted);
+    assert!(jira.versions("EX").is_empty());
+}
+
+#[test]
+fn rolled_back() {
+    let jira = ::fake::FakeJira::new();
+    jira.add_issue("EX-1", "First");
+    let version = jira.add_version("EX", "1.2.0");
+    jira.add_issue_version("EX-1", &version).unwrap();
+    let entries = [
+        Entry::project(Action::CreateVersion, "EX", &version),
+        Entry::issue(Action::AddFixVersion, "EX-1", &version),
+        Entry::issue(Action::Comment, "EX-1", &version)
+    ];
+    let mut undone = Vec::new();
+    rollback(&jira, &entries, false, |entry, what| {
+        undone.push((entry.action.clone(), what));
+    }).unwrap();
+    assert_eq!(undone, [
+        (Action::Comment, Undone::NotUndone),
+        (Action::AddFixVersion, Undone::FixVersionRemoved),
+        (Action::CreateVersion, Undone::VersionDeleted)
+    ]);
+    assert!(jira.versions("EX").is_empty());
+}
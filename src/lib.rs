@@ -0,0 +1,60 @@
+//! Creates releases on Jira from the commits of a git repository. The issue
+//! keys mentioned in the commits, e.g. `[FOO-1] Fix the thing`, are found with
+//! a `TokenParser`, then a `ReleasePlan` adds the version to every issue
+//! through a `JiraClient`, or any other implementation of `JiraApi` such as
+//! the in-memory `FakeJira`:
+//!
+//! ```no_run
+//! use jira_releaser::{Credentials, JiraClient, ReleasePlan, TokenParser};
+//! use jira_releaser::git;
+//! use jira_releaser::journal::Journal;
+//!
+//! let projects = vec![String::from("FOO")];
+//! let range = git::Range {
+//!     from: Some(String::from("master")),
+//!     to: String::from("develop")
+//! };
+//! let commits = git::logs(".", &range).unwrap();
+//! let subjects: Vec<&str> = commits.iter().map(|c| &c.summary[..]).collect();
+//! let keys = TokenParser::new(&projects).parse(&subjects.join("\n"));
+//!
+//! let client = JiraClient::new("https://jira.example.com",
+//!     Credentials::Bearer { token: String::from("secret") });
+//! let plan = ReleasePlan::new(projects, "1.2.0");
+//! let result =
+//!     plan.publish(&client, &Journal::disabled(), &keys, None).unwrap();
+//! ```
+
+#![cfg_attr(feature="clippy", feature(plugin))]
+
+#![cfg_attr(feature="clippy", plugin(clippy))]
+
+extern crate regex;
+extern crate hyper;
+extern crate git2;
+extern crate time;
+extern crate rustc_serialize;
+#[cfg(test)]
+extern crate env_logger;
+
+#[macro_use]
+extern crate log;
+
+pub mod comment;
+pub mod error;
+pub mod fake;
+pub mod git;
+pub mod jira;
+pub mod journal;
+pub mod release;
+pub mod release_notes;
+pub mod report;
+pub mod token_parser;
+
+pub use error::Error;
+pub use fake::FakeJira;
+pub use jira::{Credentials, JiraApi, JiraClient, RetryPolicy};
+pub use release::{IssueReport, IssueStatus, ProjectVersion, ReleaseComment,
+    ReleaseOptions, ReleasePlan, ReleaseResult, ReleaseRun, TransitionStatus};
+pub use token_parser::{Location, MatchMode, Mentions, Token, TokenParser,
+    TokenPattern};
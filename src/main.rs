@@ -2,453 +2,285 @@
 
 #![cfg_attr(feature="clippy", plugin(clippy))]
 
-extern crate regex;
-extern crate hyper;
+extern crate jira_releaser;
 extern crate rustc_serialize;
-#[cfg(test)]
-extern crate mockito;
-#[cfg(test)]
-extern crate iron;
-#[cfg(test)]
-extern crate router;
+extern crate time;
 
 #[macro_use]
 extern crate log;
 extern crate env_logger;
 
-use std::process::Command;
-use hyper::Client;
-use hyper::client::response::Response;
-use hyper::status::{StatusCode, StatusClass};
-
-use hyper::mime;
-
-use std::io::Read;
-use rustc_serialize::json::{self, Json};
-use std::collections::BTreeMap;
-
-use hyper::method::Method;
-use hyper::client::IntoUrl;
-use hyper::header::{Authorization, Basic, ContentType};
+use std::fs::File;
+use std::io::Write;
 
 pub mod parameters;
-mod token_parser;
-
-use parameters::Params;
-use token_parser::TokenParser;
-
-// Returns the git log diff or the latest and release branches.
-fn git_logs(params: &Params) -> Result<String, String> {
-    let mut cmd = Command::new("git");
-    let release_ptr = String::from("^") + (&params.release_branch);
-    cmd
-        .arg("log")
-        .arg(&params.latest_branch)
-        .arg(&release_ptr)
-        .arg("--no-merges")
-        .arg("--pretty=%s");
 
-    match cmd.output() {
-        Ok(output) => {
-            if output.status.success() {
-                String::from_utf8(output.stdout)
-                    .map_err(|_| "Could not parse git command \
-                             stdout".to_owned())
-            } else {
-                match String::from_utf8(output.stderr) {
-                    Ok(e) => {
-                        Err("Error running git command".to_owned() + (&e))
-                    },
-                    Err(_) => {
-                        Err("Error running git command: cannot parse \
-                            stderr".to_owned())
-                    }
-                }
-            }
-        },
-        Err(_) => {
-            Err("Git command returned an error".to_owned())
-        }
+use jira_releaser::{Error, IssueStatus, JiraClient, ReleaseOptions,
+    ReleasePlan, ReleaseResult, ReleaseRun, RetryPolicy, TokenParser,
+    TransitionStatus};
+use jira_releaser::{git, journal, report};
+use jira_releaser::journal::{Journal, Undone};
+use parameters::{Output, Params};
+
+fn release_plan(params: &Params) -> ReleasePlan {
+    ReleasePlan {
+        project_ids: params.project_ids.clone(),
+        version_name: params.version_name.clone(),
+        dry_run: params.dry_run,
+        concurrency: params.concurrency,
+        transition: params.transition.clone()
     }
 }
 
-fn send_jira_request<U: IntoUrl>(
-        client: &Client,
-        method: Method,
-        url: U,
-        params: &Params,
-        payload: Option<&str>
-        ) -> Result<Response, hyper::error::Error> {
-    let mut req = client.request(method, url);
-    if let Some(payload_str) = payload {
-        let content_type = ContentType(
-            mime::Mime(
-                mime::TopLevel::Application, mime::SubLevel::Json, Vec::new()
-            )
-        );
-        req = req
-            .header(content_type)
-            .body(payload_str);
+fn release_options(params: &Params) -> ReleaseOptions {
+    ReleaseOptions {
+        comment: params.comment,
+        comment_template: params.comment_template.clone(),
+        release_date: if params.release {
+            Some(params.release_date.clone())
+        } else {
+            None
+        },
+        repo: params.repo.clone(),
+        description: params.description.clone(),
+        notes_url: params.notes.as_ref().map(|_| params.url.clone())
     }
-    req.header(
-        Authorization(
-            Basic {
-                username: params.username.clone(),
-                password: Some(params.password.clone())
-            }
-        )
-    ).send()
 }
 
-#[derive(RustcDecodable, RustcEncodable, Clone)]
-struct JiraVersion {
-    pub name: String,
-    pub id: String
-}
-#[derive(RustcDecodable, RustcEncodable)]
-struct JiraIssueFields {
-    pub fixVersions: Vec<JiraVersion>
+fn jira_client(params: &Params) -> JiraClient {
+    JiraClient::new(&params.url, params.credentials.clone())
+        .with_retry(RetryPolicy {
+            retries: params.retries,
+            base_delay_ms: params.retry_delay,
+            ..RetryPolicy::new()
+        })
 }
 
-#[derive(RustcDecodable, RustcEncodable)]
-struct JiraIssue {
-    pub fields: JiraIssueFields
-}
-
-fn create_jira_version(client: &Client, params: &Params)
-        -> Result<JiraVersion, String> {
-    let mut map = BTreeMap::new();
-    // what is the version??
-    map.insert("name".to_owned(), Json::String(params.version_name.clone()));
-    map.insert("project".to_owned(), Json::String(params.project_id.clone()));
-    let payload_obj = Json::Object(map).to_string();
-
-    let url = params.url.to_owned() + "/rest/api/2/version";
-    debug!("creating Jira version {} through url: {}", params.version_name,
-        url);
-    debug!("POST payload: {:?}", payload_obj);
-    let res = send_jira_request(
-        client,
-        Method::Post,
-        &url,
-        params,
-        Some(&payload_obj)
-    );
-
-    match res {
-        Ok(mut res) => {
-            match res.status.class() {
-                StatusClass::Success => {
-                    let mut body_str = String::new();
-                    res.read_to_string(&mut body_str).unwrap();
-                    let version: JiraVersion =
-                        json::decode(&body_str).unwrap();
-                    Ok(version)
-                },
-                _ => {
-                    //let str_status = res.status.canonical_reason().unwrap();
-                    Err(format!("Server error creating Jira version {}", res.status))
-                }
-            }
-        },
-        Err(_) => {
-            Err("Could not request creation of Jira version".to_owned())
+fn print_dry_run(params: &Params, result: &ReleaseResult) {
+    println!("Dry run, no changes were made to Jira.");
+    for project in &result.versions {
+        if project.created {
+            println!("Would create version {} in project {}.",
+                project.version.name, project.project_id);
+        } else {
+            println!("Version {} already exists in project {}.",
+                project.version.name, project.project_id);
+        }
+        if let Some(ref date) = project.release_date {
+            println!("Would mark version {} in project {} as released on {}.",
+                project.version.name, project.project_id, date);
         }
     }
-}
-
-// Returns true if the jira version exists already. False if it needs to be
-// created.
-fn get_jira_version(client: &Client, params: &Params) 
-        -> Result<Option<JiraVersion>, String> {
-    let url = params.url.to_owned() +
-        "/rest/api/2/project/" +
-        &params.project_id + "/versions";
-    debug!("fetching jira versions for project {} with url: {}",
-        params.project_id, url);
-    let res = send_jira_request(client, Method::Get, &url, params, None);
-    match res {
-        Ok(mut res) => {
-            match res.status {
-                StatusCode::Ok => {
-                    let mut body = String::new();
-                    res.read_to_string(&mut body).unwrap();
-                    let versions: Vec<JiraVersion> =
-                        json::decode(&body).unwrap();
-                    let mut version: Option<JiraVersion> = None;
-                    for v in versions {
-                        if v.name == params.version_name {
-                            version = Some(v);
-                        }
-                    }
-                    Ok(version)
-                },
-                _ => {
-                    let msg = format!("Server error fetching Jira versions \
-                        for project {}: {}", params.project_id, res.status);
-                    Err(msg)
-                }
-            }
+    let added = result.with_status(IssueStatus::Added);
+    if added.is_empty() {
+        println!("No issues would be modified.");
+    } else {
+        println!("Would add fix version {} to issues: {}.",
+            params.version_name, added.join(", "));
+    }
+    if let Some(ref name) = params.transition {
+        let transitioned = result.with_transition(TransitionStatus::Done);
+        if !transitioned.is_empty() {
+            println!("Would transition issues to {}: {}.", name,
+                transitioned.join(", "));
         }
-        Err(_) =>
-            Err("Could not requests versions available for project".to_owned())
+    }
+    let commented = result.commented();
+    if !commented.is_empty() {
+        println!("Would comment on issues: {}.", commented.join(", "));
     }
 }
 
-// Makes a GET request and then creates the jira version if it doesnt exists.
-fn ensure_project_version(client: &Client, params: &Params)
-        -> Result<JiraVersion, String> {
-    let version = try!(get_jira_version(client, params));
-    if version.is_none() {
-        create_jira_version(client, params)
+fn write_notes(path: &str, markdown: &str) -> Result<(), Error> {
+    if path == "-" {
+        print!("{}", markdown);
+        Ok(())
     } else {
-        Ok(version.unwrap())
+        File::create(path)
+            .and_then(|mut file| file.write_all(markdown.as_bytes()))
+            .map_err(|e| Error::Io(format!("Could not write release notes \
+                to {}", path), e))
     }
 }
 
-fn generic_issue_error<E>(code: &StatusCode, issue_token: &str)
-        -> Result<E, String> {
-    Err(
-        format!(
-            "Error with issue {issue_token}: {code}",
-            code = code,
-            issue_token = issue_token
-        )
-    )
+// Undoes the changes recorded in the journal by the last run, or by every
+// run with --all-runs, the latest first.
+fn rollback(params: &Params, path: &str) -> Result<(), Error> {
+    let mut entries = try!(journal::read(path));
+    if !params.rollback_all {
+        entries = journal::last_run(entries);
+    }
+    let client = jira_client(params);
+    let dry_run = params.dry_run;
+    let would = if dry_run { "Would " } else { "" };
+    let mut not_undone: Vec<String> = Vec::new();
+    try!(journal::rollback(&client, &entries, dry_run, |entry, undone| {
+        let name = &entry.version_name;
+        let issue = entry.issue.as_ref().map_or("", |i| &i[..]);
+        let project_id = entry.project_id.as_ref().map_or("", |p| &p[..]);
+        match undone {
+            Undone::FixVersionRemoved if dry_run =>
+                println!("Would remove version {} from {}.", name, issue),
+            Undone::FixVersionRemoved =>
+                println!("Removed version {} from {}.", name, issue),
+            Undone::IssueMissing =>
+                println!("Issue {} doesn't exist anymore.", issue),
+            Undone::Unreleased =>
+                println!("{}Mark version {} in project {} as unreleased.",
+                    would, name, project_id),
+            Undone::VersionDeleted if dry_run =>
+                println!("Would delete version {} in project {} if no \
+                    issues are left in it.", name, project_id),
+            Undone::VersionDeleted =>
+                println!("Deleted version {} in project {}.", name,
+                    project_id),
+            Undone::VersionKept(left) =>
+                println!("Kept version {} in project {}, {} issues are \
+                    still in it.", name, project_id, left),
+            Undone::NotUndone => not_undone.push(issue.to_owned()),
+            Undone::Invalid => warn!("Skipping invalid journal entry {:?}",
+                entry)
+        }
+    }));
+    if !not_undone.is_empty() {
+        not_undone.reverse();
+        println!("Transitions and comments can't be rolled back, check \
+            issues: {}.", not_undone.join(", "));
+    }
+    Ok(())
 }
 
-fn get_issue_versions(client: &Client, params: &Params, issue_token: &str)
-        -> Result<Option<Vec<JiraVersion>>, String> {
-    let url = params.url.to_string() + "/rest/api/2/issue/" + issue_token;
-    debug!("fetching issue {} through url: {}", issue_token, url);
-    match send_jira_request(client, Method::Get, &url, params, None) {
-        Ok(mut res) => {
-            match res.status {
-                StatusCode::NotFound => Ok(None),
-                StatusCode::Ok => {
-                    let mut data = String::new();
-                    res.read_to_string(&mut data).unwrap();
-                    let issue: JiraIssue = json::decode(&data).unwrap();
-                    Ok(Some(issue.fields.fixVersions))
-                },
-                rest => generic_issue_error(&rest, issue_token)
-            }
-        },
-        Err(_) => {
-            Err("Error connecting to server".to_owned())
+// Prints the JSON report of a run which failed before any issue was updated,
+// so that scripts still get a document. The text output only has the error,
+// printed by main.
+fn reported<T>(params: &Params, range: Option<&git::Range>,
+        result: Result<T, Error>) -> Result<T, Error> {
+    if let Err(ref e) = result {
+        if params.output == Output::Json {
+            println!("{}", report::render_error(params.dry_run, range, e));
         }
     }
+    result
 }
 
-fn set_issue_versions(
-        client: &Client,
-        params: &Params,
-        issue_token: &str,
-        versions: Vec<JiraVersion>
-        ) -> Result<Vec<JiraVersion>, String> {
-    let url = params.url.to_string() + "/rest/api/2/issue/" + issue_token;
-    debug!("modifying issue {} through url: {}", issue_token, url);
-    let issue = JiraIssue {
-        fields: JiraIssueFields {
-            fixVersions: versions.clone()
-        }
+// Everything which can fail before Jira is changed.
+fn prepare(params: &Params)
+        -> Result<(TokenParser, git::Range, Journal), Error> {
+    let token_parser = try!(TokenParser::with_patterns(&params.project_ids,
+        &params.token_patterns))
+        .with_full_message(params.full_message)
+        .with_mode(params.match_mode);
+    let to = params.to.as_ref().unwrap_or(&params.latest_branch);
+    let range = try!(git::pick_range(&params.repo, to,
+        params.from.as_ref().map(|f| &f[..]),
+        params.previous_tag.as_ref().map(|t| &t[..]),
+        &params.release_branch, params.all_commits));
+    let journal = match params.journal {
+        Some(ref path) if !params.dry_run => try!(Journal::open(path)),
+        _ => Journal::disabled()
     };
-    let payload = json::encode(&issue).unwrap() + "
-    ";
-    let response_result =
-        send_jira_request(client, Method::Put, &url, params, Some(&payload));
-    match response_result {
-        Ok(res) => {
-            match res.status.class() {
-                StatusClass::Success => {
-                    Ok(versions)
-                },
-                _ => generic_issue_error(&res.status, issue_token)
+    Ok((token_parser, range, journal))
+}
+
+fn run(params: &Params) -> Result<(), Error> {
+    let (token_parser, range, journal) =
+        try!(reported(params, None, prepare(params)));
+    info!("reading commits in {}", range);
+    let commits = try!(reported(params, Some(&range),
+        git::logs(&params.repo, &range)));
+    // Every key once, so that each issue is only fetched and updated once.
+    let mentions = token_parser.find(&commits);
+    let issue_tokens: Vec<String> =
+        mentions.iter().map(|m| m.key.clone()).collect();
+    if log_enabled!(log::LogLevel::Debug) {
+        let mut msg = String::from("Tokens in logs: ");
+        for (i, tkn) in mentions.iter().enumerate() {
+            if i != 0 {
+                msg.push_str(", ");
             }
-        },
-        Err(_) => {
-            Err("Error connecting to server".to_owned())
+            msg.push_str(&format!("{} ({} commits)", tkn.key,
+                tkn.commits.len()));
         }
+        debug!("{}", msg);
+    }
+    let client = jira_client(params);
+    let plan = release_plan(params);
+    let ReleaseRun { mut result, notes, error } =
+        try!(reported(params, Some(&range), plan.run(&client, &journal,
+            &issue_tokens[..], &mentions, &range, &release_options(params))));
+    // Once there is a result, it is printed even if writing the notes fails.
+    let error = error.or_else(|| match (notes, params.notes.as_ref()) {
+        (Some(markdown), Some(path)) => write_notes(path, &markdown).err(),
+        _ => None
+    });
+    if params.output == Output::Text {
+        print_summary(params, &result);
+    } else {
+        println!("{}", report::render(params.dry_run, &range, &mentions,
+            &result, error.as_ref()));
+    }
+    match error.or_else(|| result.take_error()) {
+        Some(e) => Err(e),
+        None => Ok(())
     }
 }
 
-// Returns None if the issue doesnt exist. Otherwise returns a Some<Vec> with
-// the newly set versions.
-fn ensure_issue_version(
-        client: &Client,
-        params: &Params,
-        issue_token: &str,
-        version: &JiraVersion
-        ) -> Result<Option<Vec<JiraVersion>>, String> {
-
-    match try!(get_issue_versions(client, params, issue_token)) {
-        None => Ok(None),
-        Some(mut versions) => {
-            if versions.iter().any(|v| v.name == params.version_name) {
-                Ok(Some(versions))
-            } else {
-                // TODO: How do I not clone this???
-                let o_v: JiraVersion = version.clone();
-                versions.push(o_v);
-                let new_versions = try!(
-                    set_issue_versions(client, params, issue_token, versions)
-                );
-                Ok(Some(new_versions))
+fn print_summary(params: &Params, result: &ReleaseResult) {
+    if params.dry_run {
+        print_dry_run(params, result);
+    }
+    if let Some(ref name) = params.transition {
+        let unavailable = result.with_transition(TransitionStatus::Unavailable);
+        if !unavailable.is_empty() {
+            println!("Could not transition following issues to {}: {}.",
+                name, unavailable.join(", "));
+        }
+        for issue in &result.issues {
+            if let Some(ref e) = issue.transition_error {
+                println!("Transition of {} to {} failed: {}", issue.key, name,
+                    e);
             }
         }
     }
-}
-
-// Returns issue tokens which dont exist.
-pub fn publish_release<'s>(
-        client: &Client,
-        params: &Params,
-        issue_tokens: &'s [String]
-        ) -> Result<Vec<&'s str>, String> {
-    let version = try!(ensure_project_version(client, params));
-    // TODO: multihread...
-    let mut not_exist: Vec<&'s str> = Vec::new();
-    for issue_token in issue_tokens {
-        // if the issue doesnt exist then I will just notify the user that
-        // the issue found did not exist.
-        let ensured = try!(
-            ensure_issue_version(client, params, &issue_token, &version)
-        );
-        if ensured.is_none() {
-            not_exist.push(issue_token);
+    let invalid_tokens = result.with_status(IssueStatus::NotFound);
+    if invalid_tokens.len() > 0 {
+        let mut msg = String::from("Found following issues in \
+            commits not present in Jira: ");
+        for (i, tkn) in invalid_tokens.iter().enumerate() {
+            if i != 0 {
+                msg.push_str(", ");
+            }
+            msg.push_str(tkn);
         }
+        println!("{}.", msg);
     }
+    let failed = result.with_status(IssueStatus::Failed);
+    if !failed.is_empty() {
+        println!("Could not update following issues: {}.", failed.join(", "));
+    }
+}
 
-    Ok(not_exist)
+fn exit_with(e: Error) -> ! {
+    eprintln!("{}", e);
+    std::process::exit(e.exit_code());
 }
 
 fn main() {
     env_logger::init().unwrap();
-    let parser = parameters::ParamsParser::new();
-    let params: Params = parser.parse_params();
+    let parser = match parameters::ParamsParser::new() {
+        Ok(parser) => parser,
+        Err(e) => exit_with(e)
+    };
+    let params: Params = match parser.parse_params() {
+        Ok(params) => params,
+        Err(e) => exit_with(e)
+    };
     info!("params: {:?}", params);
-    let token_parser = TokenParser::new(&params.project_id);
-    match git_logs(&params) {
-        Ok(logs) => {
-            let issue_tokens = token_parser.parse(&logs);
-            if log_enabled!(log::LogLevel::Debug) {
-                let mut msg = String::from("Tokens in logs: ");
-                for (i, tkn) in issue_tokens.iter().enumerate() {
-                    if i != 0 {
-                        msg.push_str(", ");
-                    }
-                    msg.push_str(tkn);
-                }
-                debug!("{}", msg);
-            }
-            let client = Client::new();
-            match publish_release(&client, &params, &issue_tokens[..]) {
-                Ok(invalid_tokens) => {
-                    if invalid_tokens.len() > 0 {
-                        let mut msg = String::from("Found following issues in \
-                            commits not present in Jira: ");
-                        for (i, tkn) in invalid_tokens.iter().enumerate() {
-                            if i != 0 {
-                                msg.push_str(", ");
-                            }
-                            msg.push_str(tkn);
-                        }
-                        println!("{}.", msg);
-                    }
-                    std::process::exit(0);
-                },
-                Err(e) => {
-                    println!("{}", e);
-                    std::process::exit(1);
-                }
-
-            }
-        },
-        Err(e) => println!("{}", e)
+    let result = match params.rollback {
+        Some(ref path) => rollback(&params, path),
+        None => run(&params)
     };
-}
-
-
-#[cfg(test)]
-mod test {
-    use hyper::Client;
-    use parameters::Params;
-    use std::fs::File;
-    use std::io::prelude::*;
-    use std::path::Path;
-    use ::std;
-    use ::env_logger;
-    use iron::prelude::*;
-    use iron::status;
-    use router::Router;
-    use iron::mime::{Mime, TopLevel, SubLevel};
-    use iron::headers::ContentType;
-
-    fn slurp_fixture(file_path: &str) -> String {
-        let fixtures_dir = std::env::var("FIXTURES_DIR").unwrap();
-        let base_path = Path::new(&fixtures_dir);
-        let p_buf = base_path.join(file_path);
-        let p = p_buf.as_path();
-        let mut string = String::new();
-        let mut file = File::open(&p).unwrap();
-
-        file.read_to_string(&mut string).unwrap();
-        string
-    }
-
-    macro_rules! mock_route {
-        ( $router:expr, $met:ident, $url:expr, $file:expr) => {
-            $router.$met($url, |req: &mut Request| {
-                let content_type = 
-                    ContentType(Mime(TopLevel::Application, SubLevel::Json, vec![]));
-                let data = slurp_fixture($file);
-                let mut res = Response::with((status::Ok, data));
-                res.headers.set(content_type);
-                Ok(res)
-            })
-        }
-    }
-
-    fn create_fixture_router() -> Router {
-        let mut router = Router::new();
-
-        mock_route!(router, get, "/rest/api/2/issue/EX-1", "issue1_response.json");
-        mock_route!(router, get, "/rest/api/2/project/EX/versions", "versions_response.json");
-        mock_route!(router, post, "/rest/api/2/version", "create_version.json");
-
-        router.put("/rest/api/2/issue/EX-1", |_: &mut Request| {
-            Ok(Response::with((status::NoContent, "")))
-        });
-
-        router
-    }
-
-    #[test]
-    fn pull_data() {
-        env_logger::init().unwrap();
-
-
-        let mut params = Params::new();
-        params.url = "http://localhost:5000".to_owned();
-        params.project_id = "EX".to_owned();
-        params.version_name = "1.2.0".to_owned();
-        params.username = "foobar".to_owned();
-        params.password = "foobar".to_owned();
-
-        std::thread::spawn(|| {
-            let router = create_fixture_router();
-            Iron::new(router)
-                .http("localhost:5000")
-                .unwrap();
-        });
-
-        let client = Client::new();
-
-        let issue_tokens = [
-            "EX-1".to_owned(),
-            "EX-2".to_owned()
-        ];
-        let res = super::publish_release(&client, &params, &issue_tokens[..]);
-        assert!(res.is_ok(), "Did not error out");
+    if let Err(e) = result {
+        exit_with(e);
     }
 }
@@ -1,59 +1,346 @@
 
 // This file contains code which parses the parameters. It handles parsing the
-// arguments as well as falling back to environment variables. If I ever decide
-// to add a .*rc file, the code handling this will be added here.
+// arguments as well as falling back to environment variables and then to the
+// config files. The precedence is: arguments, environment variables, the
+// .jirareleaserrc at the root of the repository and finally the user's
+// config.toml under $XDG_CONFIG_HOME/jira-releaser.
 
 extern crate clap;
-use self::clap::{App, Arg};
+extern crate toml;
+use self::clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use jira_releaser::{Credentials, Error, MatchMode, TokenPattern};
+use rustc_serialize::Decodable;
 use std::env;
 use std::ffi::OsString;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use time;
 
 pub struct ParamsParser {
     username_env: Option<String>,
-    password_env: Option<String>
+    password_env: Option<String>,
+    api_token_env: Option<String>,
+    pat_env: Option<String>,
+    config: Config
+}
+
+// Everything which can be set from a config file. The keys in the file are
+// the same as the field names, e.g. `project_id = "FOO"`.
+#[derive(RustcDecodable, Default, Debug)]
+pub struct Config {
+    pub release_branch: Option<String>,
+    pub latest_branch: Option<String>,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub url: Option<String>,
+    pub project_id: Option<String>,
+    pub version_name: Option<String>,
+    pub auth_method: Option<String>,
+    pub token: Option<String>,
+    pub token_patterns: Option<Vec<PatternConfig>>
+}
+
+// A custom token pattern, given as an array of tables:
+//
+// [[token_patterns]]
+// open_tag = "feat\\("
+// close_tag = "\\):"
+//
+// Either the tags, which default to the values in pattern(), or a regex with
+// a group named inner can be given.
+#[derive(RustcDecodable, Default, Debug, Clone)]
+pub struct PatternConfig {
+    pub open_tag: Option<String>,
+    pub close_tag: Option<String>,
+    pub separator: Option<String>,
+    pub multi_separator: Option<String>,
+    pub regex: Option<String>
+}
+
+impl PatternConfig {
+    pub fn pattern(&self) -> Result<TokenPattern, Error> {
+        let tag = |value: &Option<String>, default: &str| {
+            value.clone().unwrap_or_else(|| default.to_owned())
+        };
+        let tags = [&self.open_tag, &self.close_tag, &self.separator,
+            &self.multi_separator];
+        match self.regex {
+            Some(_) if tags.iter().any(|t| t.is_some()) =>
+                Err(Error::Config(String::from("A token pattern can't have \
+                    both a regex and tags"))),
+            Some(ref regex) => Ok(TokenPattern::Regex(regex.clone())),
+            None => Ok(TokenPattern::Tags {
+                open_tag: tag(&self.open_tag, ""),
+                close_tag: tag(&self.close_tag, ""),
+                separator: tag(&self.separator, "-"),
+                multi_separator: tag(&self.multi_separator, "[ ]*,[ ]*")
+            })
+        }
+    }
+}
+
+impl Config {
+    pub fn parse(source: &str, name: &str) -> Result<Config, Error> {
+        let mut parser = toml::Parser::new(source);
+        let table = match parser.parse() {
+            Some(table) => table,
+            None => {
+                let error = &parser.errors[0];
+                let (line, col) = parser.to_linecol(error.lo);
+                return Err(Error::Config(format!("Error parsing config file {} \
+                    at line {}, column {}: {}", name, line + 1, col + 1,
+                    error)));
+            }
+        };
+        let mut decoder = toml::Decoder::new(toml::Value::Table(table));
+        let config = try!(
+            Config::decode(&mut decoder)
+                .map_err(|e| Error::Config(format!("Invalid config file {}: \
+                    {}", name, e)))
+        );
+        if let Some(toml::Value::Table(unknown)) = decoder.toml {
+            if let Some(key) = unknown.keys().next() {
+                return Err(Error::Config(format!("Unknown key in config file \
+                    {}: {}", name, key)));
+            }
+        }
+        Ok(config)
+    }
+
+    // Returns None if the file doesn't exist. A file which exists but can't
+    // be read is an error.
+    pub fn load(path: &Path) -> Result<Option<Config>, Error> {
+        let name = path.display();
+        let mut file = match File::open(path) {
+            Ok(file) => file,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound =>
+                return Ok(None),
+            Err(e) => return Err(Error::Config(format!("Could not open \
+                config file {}: {}", name, e)))
+        };
+        let mut source = String::new();
+        try!(
+            file.read_to_string(&mut source)
+                .map_err(|e| Error::Config(format!("Could not read config \
+                    file {}: {}", name, e)))
+        );
+        Config::parse(&source, &name.to_string()).map(Some)
+    }
+
+    // The .jirareleaserrc file in the root of the repository containing the
+    // directory, which is the one given with --repo.
+    pub fn repo_path(repo: &Path) -> Option<PathBuf> {
+        let start = match repo.canonicalize() {
+            Ok(start) => start,
+            Err(_) => return None
+        };
+        let mut dir: Option<&Path> = Some(&start);
+        while let Some(d) = dir {
+            if d.join(".git").exists() {
+                return Some(d.join(".jirareleaserrc"));
+            }
+            dir = d.parent();
+        }
+        None
+    }
+
+    pub fn user_path() -> Option<PathBuf> {
+        let config_home = env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| env::home_dir().map(|home| home.join(".config")));
+        config_home.map(|dir| dir.join("jira-releaser").join("config.toml"))
+    }
+
+    // Fills in the values missing from this config with the ones from the
+    // fallback.
+    pub fn or(self, fallback: Config) -> Config {
+        Config {
+            release_branch: self.release_branch.or(fallback.release_branch),
+            latest_branch: self.latest_branch.or(fallback.latest_branch),
+            username: self.username.or(fallback.username),
+            password: self.password.or(fallback.password),
+            url: self.url.or(fallback.url),
+            project_id: self.project_id.or(fallback.project_id),
+            version_name: self.version_name.or(fallback.version_name),
+            auth_method: self.auth_method.or(fallback.auth_method),
+            token: self.token.or(fallback.token),
+            token_patterns: self.token_patterns.or(fallback.token_patterns)
+        }
+    }
+}
+
+// The value of --repo, read before the arguments are parsed since the config
+// of the repository provides their defaults.
+fn repo_arg<I, T>(args: I) -> PathBuf
+        where I: IntoIterator<Item=T>, T: Into<OsString> {
+    let mut args = args.into_iter().map(|arg| arg.into());
+    while let Some(arg) = args.next() {
+        let repo = match arg.to_str() {
+            Some("--repo") => args.next(),
+            Some(arg) => arg.strip_prefix("--repo=").map(OsString::from),
+            None => None
+        };
+        if let Some(repo) = repo {
+            return PathBuf::from(repo);
+        }
+    }
+    PathBuf::from(".")
+}
+
+fn load_optional(path: Option<PathBuf>) -> Result<Config, Error> {
+    match path {
+        Some(path) => Config::load(&path).map(|c| c.unwrap_or_default()),
+        None => Ok(Config::default())
+    }
+}
+
+fn validate_concurrency(value: String) -> Result<(), String> {
+    match value.parse::<usize>() {
+        Ok(n) if n > 0 => Ok(()),
+        _ => Err(String::from("must be a number greater than 0"))
+    }
+}
+
+fn validate_number(value: String) -> Result<(), String> {
+    match value.parse::<u32>() {
+        Ok(_) => Ok(()),
+        _ => Err(String::from("must be a number"))
+    }
+}
+
+fn validate_release_date(value: String) -> Result<(), String> {
+    if value == "today" || value == "commit" {
+        return Ok(());
+    }
+    match time::strptime(&value, "%Y-%m-%d") {
+        Ok(_) => Ok(()),
+        Err(_) => Err(String::from("must be a date as YYYY-MM-DD, today or \
+            commit"))
+    }
+}
+
+// Uses the first fallback available as the default value, otherwise the
+// argument must be given on the command line.
+// The journal written when --journal isn't given, e.g. FOO-1.2.0.journal in
+// the current directory. The characters which can't be in a file name are
+// replaced by underscores.
+fn default_journal(project_ids: &[String], version_name: &str) -> String {
+    let name: String = format!("{}-{}", project_ids.join("-"), version_name)
+        .chars()
+        .map(|c| if c.is_alphanumeric() || "._-".contains(c) { c } else { '_' })
+        .collect();
+    format!("{}.journal", name)
+}
+
+fn with_fallback<'a>(arg: Arg<'a, 'a>, fallbacks: &[&'a Option<String>])
+        -> Arg<'a, 'a> {
+    match fallbacks.iter().filter_map(|v| v.as_ref()).next() {
+        Some(value) => arg.default_value(value),
+        None => arg.required(true)
+    }
+}
+
+// Same as with_fallback, but leaves the argument optional when there is no
+// fallback.
+fn optional_fallback<'a>(arg: Arg<'a, 'a>, fallbacks: &[&'a Option<String>])
+        -> Arg<'a, 'a> {
+    match fallbacks.iter().filter_map(|v| v.as_ref()).next() {
+        Some(value) => arg.default_value(value),
+        None => arg
+    }
+}
+
+// How the outcome of a run is printed.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Output {
+    // Sentences about what was done, for people.
+    Text,
+    // A single JSON document on stdout, for scripts.
+    Json
 }
 
 #[derive(Debug)]
 pub struct Params {
+    pub repo: String,
     pub release_branch: String,
     pub latest_branch: String,
-    pub username: String,
-    pub password: String,
+    pub from: Option<String>,
+    pub to: Option<String>,
+    pub previous_tag: Option<String>,
+    // Releases the whole history of --to when there is no lower bound.
+    pub all_commits: bool,
+    pub credentials: Credentials,
     pub url: String,
-    pub project_id: String,
-    pub version_name: String
-}
-impl Params {
-    pub fn new () -> Params {
-        Params {
-            release_branch: String::from(""),
-            latest_branch: String::from(""),
-            username: String::from(""),
-            password: String::from(""),
-            url: String::from(""),
-            project_id: String::from(""),
-            version_name: String::from("")
-        }
-    }
+    pub project_ids: Vec<String>,
+    pub version_name: String,
+    pub dry_run: bool,
+    pub concurrency: usize,
+    pub retries: u32,
+    // The delay before the first retry, in milliseconds.
+    pub retry_delay: u64,
+    pub notes: Option<String>,
+    pub release: bool,
+    pub release_date: String,
+    pub description: Option<String>,
+    pub transition: Option<String>,
+    pub comment: bool,
+    pub comment_template: Option<String>,
+    pub journal: Option<String>,
+    pub output: Output,
+    pub full_message: bool,
+    pub match_mode: MatchMode,
+    // The custom patterns from the config, added to the built-in ones.
+    pub token_patterns: Vec<TokenPattern>,
+    // The journal to roll back, when running the rollback command instead of
+    // a release.
+    pub rollback: Option<String>,
+    // Rolls back every run of the journal instead of the last one.
+    pub rollback_all: bool
 }
 
 impl ParamsParser {
-    pub fn new<'n>() -> ParamsParser {
-        ParamsParser {
+    pub fn new() -> Result<ParamsParser, Error> {
+        let user_config = try!(load_optional(Config::user_path()));
+        let repo = repo_arg(env::args_os());
+        let repo_config = try!(load_optional(Config::repo_path(&repo)));
+        Ok(ParamsParser {
             username_env: env::var("JIRA_USERNAME").ok(),
-            password_env: env::var("JIRA_PASSWORD").ok()
-        }
+            password_env: env::var("JIRA_PASSWORD").ok(),
+            api_token_env: env::var("JIRA_API_TOKEN").ok(),
+            pat_env: env::var("JIRA_PAT").ok(),
+            config: repo_config.or(user_config)
+        })
     }
     fn get_app(&self) -> App {
         App::new("Jira Release Tool")
             .version("0.2.0")
             .author("Jonathan Boudreau")
+            .setting(AppSettings::SubcommandsNegateReqs)
+            .subcommand(SubCommand::with_name("rollback")
+                .about("Undoes the changes recorded in a journal by the last \
+                    run: removes the fix versions added and deletes the \
+                    versions created if no issues are left in them.")
+                .arg(Arg::with_name("Journal")
+                     .required(true)
+                     .help("The journal written by --journal."))
+                .arg(Arg::with_name("All runs")
+                     .long("all-runs")
+                     .help("Undo every run appended to the journal, not only \
+                        the last one.")))
+            .arg(Arg::with_name("Repository")
+                 .long("repo")
+                 .takes_value(true)
+                 .default_value(".")
+                 .help("Path to the git repository to read the commits \
+                    from."))
             .arg(Arg::with_name("Release branch")
                  .short("r")
                  .long("release-branch")
                  .takes_value(true)
                  .required(true)
-                 .default_value("master")
+                 .default_value(self.config.release_branch.as_ref()
+                    .map_or("master", |b| b))
                  .help("The branch which once the release is created, \
                        will be merged into"))
             .arg(Arg::with_name("Latest branch")
@@ -61,29 +348,177 @@ impl ParamsParser {
                 .long("latest-branch")
                 .takes_value(true)
                 .required(true)
-                .default_value("develop")
+                .default_value(self.config.latest_branch.as_ref()
+                    .map_or("develop", |b| b))
                 .help("The branch which is going to be merged to trigger \
                     the release"))
-            .arg(Arg::with_name("Jira URL")
+            .arg(Arg::with_name("From")
+                 .long("from")
+                 .takes_value(true)
+                 .conflicts_with("Previous tag")
+                 .help("Release the commits after this revision, e.g. the \
+                    tag of the previous release. Replaces the release \
+                    branch."))
+            .arg(Arg::with_name("To")
+                 .long("to")
+                 .takes_value(true)
+                 .help("Release the commits up to this revision. Replaces \
+                    the latest branch."))
+            .arg(Arg::with_name("Previous tag")
+                 .long("previous-tag")
+                 .takes_value(true)
+                 .help("Release the commits after the closest tag matching \
+                    this pattern, e.g. 'v*', which is an ancestor of the \
+                    commits released."))
+            .arg(Arg::with_name("All commits")
+                 .long("all-commits")
+                 .conflicts_with("From")
+                 .help("Release the whole history of the latest branch, or \
+                    of --to, e.g. for a first release. Replaces the release \
+                    branch, and is used when --previous-tag matches no tag."))
+            .arg(with_fallback(Arg::with_name("Jira URL")
                   .short("U")
                   .long("url")
                   .takes_value(true)
-                  .required(true)
-                  .help("This is the api root url for your Jira project."))
-            .arg(Arg::with_name("Project Id")
+                  .help("This is the api root url for your Jira project."),
+                  &[&self.config.url]))
+            .arg(with_fallback(Arg::with_name("Project Id")
                  .short("P")
                  .long("project-id")
                  .takes_value(true)
-                 .required(true)
-                 .help("Project id or key on Jira"))
-            .arg(Arg::with_name("Version name")
+                 .multiple(true)
+                 .help("Project key on Jira, e.g. FOO. Can be given more \
+                    than once, or as a comma separated list, to release \
+                    several projects."),
+                 &[&self.config.project_id]))
+            .arg(with_fallback(Arg::with_name("Version name")
                  .short("v")
                  .long("version-name")
                  .takes_value(true)
-                 .required(true)
-                 .help("The version name to use for the release."))
+                 .help("The version name to use for the release."),
+                 &[&self.config.version_name]))
+            .arg(Arg::with_name("Full message")
+                 .long("full-message")
+                 .help("Also look for issue keys in the body of the commit \
+                    messages, at the start of a line or in trailers such as \
+                    'Refs: FOO-1' and 'Jira: FOO-1'."))
+            .arg(Arg::with_name("Match")
+                 .long("match")
+                 .takes_value(true)
+                 .possible_values(&["strict", "loose"])
+                 .default_value("strict")
+                 .help("strict only finds the issue keys at the start of a \
+                    line, as in '[FOO-1] msg'. loose finds every key, as a \
+                    whole word, anywhere in a line."))
+            .arg(Arg::with_name("Dry run")
+                 .long("dry-run")
+                 .help("Only print the version which would be created and \
+                    the issues which would be modified, without changing \
+                    anything on Jira."))
+            .arg(Arg::with_name("Concurrency")
+                 .long("concurrency")
+                 .takes_value(true)
+                 .default_value("1")
+                 .validator(validate_concurrency)
+                 .help("How many issues to update on Jira at the same time."))
+            .arg(Arg::with_name("Retries")
+                 .long("retries")
+                 .takes_value(true)
+                 .default_value("3")
+                 .validator(validate_number)
+                 .help("How many times to retry a request when Jira can't be \
+                    reached or answers with 429 or a 5xx status. Creating \
+                    versions, transitions and comments are only retried on \
+                    429."))
+            .arg(Arg::with_name("Retry delay")
+                 .long("retry-delay")
+                 .takes_value(true)
+                 .value_name("Milliseconds")
+                 .default_value("500")
+                 .validator(validate_number)
+                 .help("Delay before the first retry, doubled on every \
+                    attempt. A Retry-After header from Jira takes \
+                    precedence."))
+            .arg(Arg::with_name("Notes")
+                 .long("notes")
+                 .takes_value(true)
+                 .help("Write release notes in Markdown for the issues \
+                    found to this file, or to stdout when it is -."))
+            .arg(Arg::with_name("Release")
+                 .long("release")
+                 .help("Mark the version as released once the issues are \
+                    updated."))
+            .arg(Arg::with_name("Release date")
+                 .long("release-date")
+                 .takes_value(true)
+                 .default_value("today")
+                 .validator(validate_release_date)
+                 .help("Date to release the version on with --release, as \
+                    YYYY-MM-DD. 'today' and 'commit', the date of the last \
+                    commit released, are also accepted."))
+            .arg(Arg::with_name("Description")
+                 .long("description")
+                 .takes_value(true)
+                 .help("Description set on the version with --release."))
+            .arg(Arg::with_name("Transition")
+                 .long("transition")
+                 .takes_value(true)
+                 .help("Move every issue through the workflow transition \
+                    with this name, or to the status with this name, once \
+                    it has the version. Issues where it isn't available are \
+                    reported."))
+            .arg(Arg::with_name("Comment")
+                 .long("comment")
+                 .help("Comment on every issue which gets the version with \
+                    the commits mentioning it."))
+            .arg(Arg::with_name("Comment template")
+                 .long("comment-template")
+                 .takes_value(true)
+                 .value_name("File")
+                 .requires("Comment")
+                 .help("File with the template of the comment. {version}, \
+                    {range} and {commits} are replaced by the version name, \
+                    the range of commits and the commits mentioning the \
+                    issue."))
+            .arg(Arg::with_name("Journal")
+                 .long("journal")
+                 .takes_value(true)
+                 .value_name("File")
+                 .help("Append every change made to Jira to this file, so \
+                    that it can be undone with the rollback command. \
+                    Defaults to <projects>-<version>.journal in the current \
+                    directory."))
+            .arg(Arg::with_name("No journal")
+                 .long("no-journal")
+                 .conflicts_with("Journal")
+                 .help("Don't write a journal. The run can't be rolled back \
+                    then."))
+            .arg(Arg::with_name("Output")
+                 .long("output")
+                 .takes_value(true)
+                 .possible_values(&["text", "json"])
+                 .default_value("text")
+                 .help("Print a summary of the run as text, or as a JSON \
+                    report with the range, the issues found, the versions \
+                    and the status of every issue."))
+            .arg(Arg::with_name("Auth method")
+                 .long("auth-method")
+                 .takes_value(true)
+                 .possible_values(&["basic", "token", "bearer"])
+                 .default_value(self.config.auth_method.as_ref()
+                    .map_or("basic", |m| m))
+                 .help("How to authenticate with Jira: basic uses the \
+                    username and password, token the username and an API \
+                    token and bearer a personal access token."))
             .arg(self.username_arg())
             .arg(self.password_arg())
+            .arg(Arg::with_name("Token")
+                 .long("token")
+                 .takes_value(true)
+                 .help("API token or personal access token for the token \
+                    and bearer auth methods. Falls back to the \
+                    JIRA_API_TOKEN or JIRA_PAT environment variable \
+                    respectively."))
     }
 
     fn username_arg(&self) -> Arg {
@@ -92,11 +527,8 @@ impl ParamsParser {
              .long("username")
              .takes_value(true)
              .help("Your Jira username. Falls back to the JIRA_USERNAME \
-                environment variable");
-        match self.username_env.as_ref() {
-            Some(username) => arg.default_value(username),
-            None => arg.required(true)
-        }
+                environment variable. Not needed for bearer auth");
+        optional_fallback(arg, &[&self.username_env, &self.config.username])
     }
 
     fn password_arg(&self) -> Arg {
@@ -106,39 +538,152 @@ impl ParamsParser {
             .takes_value(true)
             .help("Jira password. Falls back to JIRA_PASSWORD environment \
                 variable");
-        match self.password_env.as_ref() {
-            Some(password) => arg.default_value(password),
-            None => arg.required(true)
+        optional_fallback(arg, &[&self.password_env, &self.config.password])
+    }
+
+    fn credentials(&self, matches: &ArgMatches) -> Result<Credentials, Error> {
+        let method = matches.value_of("Auth method").unwrap();
+        let missing = |what: &str| Error::Config(
+            format!("{} is required for {} auth", what, method)
+        );
+        let value = |key: &str, env: &Option<String>, config: &Option<String>| {
+            matches.value_of(key)
+                .map(String::from)
+                .or_else(|| env.clone())
+                .or_else(|| config.clone())
+        };
+        let username = || matches.value_of("Username")
+            .map(String::from)
+            .ok_or_else(|| missing("A username"));
+        match method {
+            "basic" => Ok(Credentials::Basic {
+                username: try!(username()),
+                password: try!(
+                    matches.value_of("Password")
+                        .map(String::from)
+                        .ok_or_else(|| missing("A password"))
+                )
+            }),
+            "token" => Ok(Credentials::Token {
+                username: try!(username()),
+                token: try!(
+                    value("Token", &self.api_token_env, &self.config.token)
+                        .ok_or_else(|| missing("An API token"))
+                )
+            }),
+            "bearer" => Ok(Credentials::Bearer {
+                token: try!(
+                    value("Token", &self.pat_env, &self.config.token)
+                        .ok_or_else(|| missing("A personal access token"))
+                )
+            }),
+            _ => Err(Error::Config(format!("Unknown auth method {}", method)))
         }
     }
 
-    pub fn parse_str<I, T>(&self, itr: I) -> Params 
+    pub fn parse_str<I, T>(&self, itr: I) -> Result<Params, Error>
             where I: IntoIterator<Item=T>, T: Into<OsString> {
         let app = self.get_app();
         let matches = app.get_matches_from(itr);
         let from_key = |s: &str| matches.value_of(s).unwrap().to_owned();
-        Params {
-            username: from_key("Username"),
-            password: from_key("Password"),
-            url: from_key("Jira URL"),
+        // The arguments only required for a release are missing when rolling
+        // back.
+        let or_empty = |s: &str| matches.value_of(s).unwrap_or("").to_owned();
+        let rollback_matches = matches.subcommand_matches("rollback");
+        let rollback = rollback_matches
+            .map(|sub| sub.value_of("Journal").unwrap().to_owned());
+        let output = match matches.value_of("Output").unwrap() {
+            "json" => Output::Json,
+            _ => Output::Text
+        };
+        if output == Output::Json && matches.value_of("Notes") == Some("-") {
+            return Err(Error::Config(String::from("Release notes can't be \
+                written to stdout with the JSON output")));
+        }
+        if rollback.is_some() && matches.value_of("Jira URL").is_none() {
+            return Err(Error::Config(String::from("A Jira URL is required to \
+                roll back")));
+        }
+        let mut token_patterns = Vec::new();
+        for pattern in self.config.token_patterns.iter().flat_map(|p| p) {
+            token_patterns.push(try!(pattern.pattern()));
+        }
+        let project_ids: Vec<String> = matches.values_of("Project Id")
+            .into_iter()
+            .flat_map(|ids| ids)
+            .flat_map(|ids| ids.split(','))
+            .map(|id| id.trim().to_owned())
+            .filter(|id| !id.is_empty())
+            .collect();
+        let version_name = or_empty("Version name");
+        // A release is always journaled unless asked not to, so that it can
+        // be rolled back even when it wasn't planned to.
+        let journal = if rollback.is_some() ||
+                matches.is_present("No journal") {
+            None
+        } else {
+            Some(matches.value_of("Journal").map_or_else(
+                || default_journal(&project_ids, &version_name), String::from))
+        };
+        Ok(Params {
+            repo: from_key("Repository"),
+            credentials: try!(self.credentials(&matches)),
+            url: or_empty("Jira URL"),
             release_branch: from_key("Release branch"),
             latest_branch: from_key("Latest branch"),
-            project_id: from_key("Project Id"),
-            version_name: from_key("Version name")
-        }
+            from: matches.value_of("From").map(String::from),
+            to: matches.value_of("To").map(String::from),
+            previous_tag: matches.value_of("Previous tag").map(String::from),
+            all_commits: matches.is_present("All commits"),
+            project_ids: project_ids,
+            version_name: version_name,
+            dry_run: matches.is_present("Dry run"),
+            concurrency: from_key("Concurrency").parse().unwrap(),
+            retries: from_key("Retries").parse().unwrap(),
+            retry_delay: from_key("Retry delay").parse().unwrap(),
+            notes: matches.value_of("Notes").map(String::from),
+            release: matches.is_present("Release"),
+            release_date: from_key("Release date"),
+            description: matches.value_of("Description").map(String::from),
+            transition: matches.value_of("Transition").map(String::from),
+            comment: matches.is_present("Comment"),
+            comment_template: matches.value_of("Comment template")
+                .map(String::from),
+            journal: journal,
+            output: output,
+            full_message: matches.is_present("Full message"),
+            match_mode: match matches.value_of("Match").unwrap() {
+                "loose" => MatchMode::Loose,
+                _ => MatchMode::Strict
+            },
+            token_patterns: token_patterns,
+            rollback: rollback,
+            rollback_all: rollback_matches
+                .map_or(false, |sub| sub.is_present("All runs"))
+        })
     }
 
-    pub fn parse_params(&self) -> Params {
+    pub fn parse_params(&self) -> Result<Params, Error> {
         self.parse_str(env::args_os())
     }
 }
 
+// A parser without config files, with a username and password in the
+// environment but no tokens.
+#[cfg(test)]
+fn test_parser() -> ParamsParser {
+    ParamsParser {
+        username_env: Some(String::from("Hai")),
+        password_env: Some(String::from("123")),
+        api_token_env: None,
+        pat_env: None,
+        config: Config::default()
+    }
+}
+
 #[test]
 fn simple_parser() {
-    let parser = ParamsParser {
-        username_env: None,
-        password_env: None
-    };
+    let parser = test_parser();
     let args = vec![
         "program",
         "--username", "Foobar",
@@ -149,26 +694,299 @@ fn simple_parser() {
         "--project-id", "NOOB-9000",
         "--version-name", "1.1.1"
     ];
-    let params = parser.parse_str(&args);
-    assert_eq!(&params.username, "Foobar");
+    let params = parser.parse_str(&args).unwrap();
+    assert_eq!(params.credentials, Credentials::Basic {
+        username: String::from("Foobar"),
+        password: String::from("123")
+    });
     assert_eq!(&params.release_branch, "master");
+    assert!(!params.dry_run);
+    assert_eq!(&params.repo, ".");
+    assert!(params.from.is_none());
+    assert!(!params.release);
+    assert_eq!(&params.release_date, "today");
+    assert_eq!(params.concurrency, 1);
+    assert_eq!(params.retries, 3);
+    assert_eq!(params.output, Output::Text);
 }
 
 #[test]
 fn with_env() {
-    let parser = ParamsParser {
-        username_env: Some(String::from("Hai")),
-        password_env: Some(String::from("123"))
-    };
+    let parser = test_parser();
     let args = vec![
         "program",
         "--release-branch", "foobar",
         "--url", "http://doodle.com",
         "--project-id", "WTF-2",
-        "--version-name", "1.1.1"
+        "--version-name", "1.1.1",
+        "--dry-run",
+        "--concurrency", "8",
+        "--retries", "5",
+        "--retry-delay", "100",
+        "--notes", "-",
+        "--all-commits"
     ];
-    let params = parser.parse_str(&args);
-    assert_eq!(&params.username, "Hai");
+    let params = parser.parse_str(&args).unwrap();
+    assert_eq!(params.credentials, Credentials::Basic {
+        username: String::from("Hai"),
+        password: String::from("123")
+    });
+    assert!(params.dry_run);
+    assert_eq!(params.concurrency, 8);
+    assert_eq!(params.retries, 5);
+    assert_eq!(params.retry_delay, 100);
+    assert!(params.all_commits);
+    assert_eq!(params.notes, Some(String::from("-")));
     assert_eq!(&params.latest_branch, "develop");
     assert_eq!(&params.release_branch, "foobar");
 }
+
+#[test]
+fn config_file() {
+    let config = Config::parse("
+        url = \"http://doodle.com\"
+        project_id = \"WTF\"
+        release_branch = \"stable\"
+    ", "test").unwrap();
+    assert_eq!(config.url, Some(String::from("http://doodle.com")));
+    assert_eq!(config.release_branch, Some(String::from("stable")));
+    assert!(config.username.is_none());
+
+    let config = Config::parse("
+        [[token_patterns]]
+        close_tag = \":\"
+        multi_separator = \"/\"
+
+        [[token_patterns]]
+        regex = \"^fixes (?P<inner>.+)$\"
+    ", "test").unwrap();
+    let patterns: Vec<TokenPattern> = config.token_patterns.unwrap()
+        .iter()
+        .map(|p| p.pattern().unwrap())
+        .collect();
+    assert_eq!(patterns, [
+        TokenPattern::Tags {
+            open_tag: String::from(""),
+            close_tag: String::from(":"),
+            separator: String::from("-"),
+            multi_separator: String::from("/")
+        },
+        TokenPattern::Regex(String::from("^fixes (?P<inner>.+)$"))
+    ]);
+    let both = Config::parse("
+        [[token_patterns]]
+        open_tag = \"x\"
+        regex = \"(?P<inner>.+)\"
+    ", "test").unwrap();
+    assert!(both.token_patterns.unwrap()[0].pattern().is_err());
+    assert!(Config::parse("[[token_patterns]]\nopen = \"x\"", "test")
+        .is_err());
+
+    assert!(Config::load(Path::new("no/such/config.toml")).unwrap()
+        .is_none());
+    // A directory exists, but isn't a config file.
+    assert!(Config::load(Path::new("src")).is_err());
+
+    assert!(Config::parse("url = ", "test").is_err());
+    assert!(Config::parse("uri = \"http://doodle.com\"", "test").is_err());
+}
+
+#[test]
+fn repo_config_path() {
+    assert_eq!(repo_arg(&["program", "--url", "x"]), PathBuf::from("."));
+    assert_eq!(repo_arg(&["program", "--repo", "../app", "--url", "x"]),
+        PathBuf::from("../app"));
+    assert_eq!(repo_arg(&["program", "--repo=../app"]),
+        PathBuf::from("../app"));
+    assert!(Config::repo_path(Path::new("no/such/repo")).is_none());
+}
+
+#[test]
+fn config_precedence() {
+    let repo_config = Config {
+        password: Some(String::from("repo")),
+        project_id: Some(String::from("REPO")),
+        ..Config::default()
+    };
+    let user_config = Config {
+        username: Some(String::from("user")),
+        password: Some(String::from("user")),
+        project_id: Some(String::from("USER")),
+        version_name: Some(String::from("1.0.0")),
+        url: Some(String::from("http://doodle.com")),
+        latest_branch: Some(String::from("next")),
+        ..Config::default()
+    };
+    let parser = ParamsParser {
+        username_env: Some(String::from("env")),
+        password_env: None,
+        config: repo_config.or(user_config),
+        ..test_parser()
+    };
+    let args = vec![
+        "program",
+        "--version-name", "1.1.1"
+    ];
+    let params = parser.parse_str(&args).unwrap();
+    assert_eq!(&params.version_name, "1.1.1");
+    assert_eq!(params.credentials, Credentials::Basic {
+        username: String::from("env"),
+        password: String::from("repo")
+    });
+    assert_eq!(params.project_ids, ["REPO"]);
+    assert_eq!(&params.url, "http://doodle.com");
+    assert_eq!(&params.latest_branch, "next");
+    assert_eq!(&params.release_branch, "master");
+}
+
+#[test]
+fn auth_methods() {
+    let parser = ParamsParser {
+        username_env: None,
+        password_env: None,
+        api_token_env: Some(String::from("api-token")),
+        pat_env: Some(String::from("pat")),
+        ..test_parser()
+    };
+    let args = |extra: &[&'static str]| {
+        let mut args = vec![
+            "program",
+            "--url", "http://doodle.com",
+            "--project-id", "WTF",
+            "--version-name", "1.1.1"
+        ];
+        args.extend_from_slice(extra);
+        args
+    };
+
+    let params = parser.parse_str(&args(&["--auth-method", "bearer"]));
+    assert_eq!(params.unwrap().credentials, Credentials::Bearer {
+        token: String::from("pat")
+    });
+
+    let params = parser.parse_str(
+        &args(&["--auth-method", "token", "--username", "me"])
+    );
+    assert_eq!(params.unwrap().credentials, Credentials::Token {
+        username: String::from("me"),
+        token: String::from("api-token")
+    });
+
+    let params = parser.parse_str(
+        &args(&["--auth-method", "bearer", "--token", "given"])
+    );
+    assert_eq!(params.unwrap().credentials, Credentials::Bearer {
+        token: String::from("given")
+    });
+
+    assert!(parser.parse_str(&args(&["--auth-method", "token"])).is_err());
+    assert!(parser.parse_str(&args(&["--username", "me"])).is_err());
+}
+
+#[test]
+fn multiple_projects() {
+    let parser = test_parser();
+    let args = vec![
+        "program",
+        "--url", "http://doodle.com",
+        "--project-id", "API",
+        "--project-id", "WEB, OPS",
+        "--version-name", "1.1.1",
+        "--previous-tag", "v*",
+        "--to", "v1.1.1",
+        "--release",
+        "--release-date", "2016-07-02",
+        "--transition", "Released",
+        "--full-message",
+        "--match", "loose",
+        "--comment",
+        "--comment-template", "comment.txt"
+    ];
+    let params = parser.parse_str(&args).unwrap();
+    assert_eq!(params.previous_tag, Some(String::from("v*")));
+    assert!(!params.all_commits);
+    assert_eq!(params.to, Some(String::from("v1.1.1")));
+    assert!(params.release);
+    assert_eq!(&params.release_date, "2016-07-02");
+    assert_eq!(params.transition, Some(String::from("Released")));
+    assert!(params.full_message);
+    assert_eq!(params.match_mode, MatchMode::Loose);
+    assert!(params.comment);
+    assert_eq!(params.comment_template, Some(String::from("comment.txt")));
+    assert_eq!(params.project_ids, ["API", "WEB", "OPS"]);
+}
+
+#[test]
+fn rollback_command() {
+    let parser = test_parser();
+    let params = parser.parse_str(&[
+        "program", "--url", "http://doodle.com", "rollback", "run.journal"
+    ]).unwrap();
+    assert_eq!(params.rollback, Some(String::from("run.journal")));
+    assert!(!params.rollback_all);
+    assert!(params.project_ids.is_empty());
+    let all = parser.parse_str(&[
+        "program", "--url", "http://doodle.com", "rollback", "--all-runs",
+        "run.journal"
+    ]).unwrap();
+    assert!(all.rollback_all);
+    assert_eq!(&params.version_name, "");
+
+    assert!(parser.parse_str(&["program", "rollback", "run.journal"])
+        .is_err());
+    let release = parser.parse_str(&[
+        "program",
+        "--url", "http://doodle.com",
+        "--project-id", "API",
+        "--version-name", "1.1.1",
+        "--journal", "run.journal"
+    ]).unwrap();
+    assert_eq!(release.journal, Some(String::from("run.journal")));
+    assert!(release.rollback.is_none());
+    assert!(params.journal.is_none());
+}
+
+#[test]
+fn default_journal_path() {
+    let parser = test_parser();
+    let args = |extra: &[&'static str]| {
+        let mut args = vec![
+            "program",
+            "--url", "http://doodle.com",
+            "--project-id", "API,WEB",
+            "--version-name", "release/1.1"
+        ];
+        args.extend(extra);
+        parser.parse_str(&args).unwrap()
+    };
+    assert_eq!(args(&[]).journal,
+        Some(String::from("API-WEB-release_1.1.journal")));
+    assert!(args(&["--no-journal"]).journal.is_none());
+}
+
+#[test]
+fn json_output() {
+    let parser = test_parser();
+    let args = |extra: &[&'static str]| {
+        let mut args = vec![
+            "program",
+            "--url", "http://doodle.com",
+            "--project-id", "API",
+            "--version-name", "1.1.1",
+            "--output", "json"
+        ];
+        args.extend(extra);
+        args
+    };
+    let params = parser.parse_str(&args(&["--notes", "notes.md"])).unwrap();
+    assert_eq!(params.output, Output::Json);
+    assert!(parser.parse_str(&args(&["--notes", "-"])).is_err());
+}
+
+#[test]
+fn release_dates() {
+    assert!(validate_release_date(String::from("today")).is_ok());
+    assert!(validate_release_date(String::from("commit")).is_ok());
+    assert!(validate_release_date(String::from("2016-07-02")).is_ok());
+    assert!(validate_release_date(String::from("07/02/2016")).is_err());
+}
@@ -0,0 +1,886 @@
+//! Publishes a release on Jira: creates the version in every project, adds it
+//! as a fix version to the issues and optionally moves them through the
+//! workflow, comments on them and marks the version as released.
+
+use comment;
+use error::Error;
+use git;
+use git::Range;
+use jira::{JiraApi, JiraIssueFields, JiraVersion, SEARCH_CHUNK};
+use journal::{Action, Entry, Journal};
+use release_notes;
+use release_notes::Note;
+use std::cmp;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use time;
+use token_parser::{self, Mentions};
+
+/// What to release, and how.
+#[derive(Debug, Clone)]
+pub struct ReleasePlan {
+    /// The keys of the projects to create the version in.
+    pub project_ids: Vec<String>,
+    pub version_name: String,
+    /// When true, nothing is sent to Jira except GET requests.
+    pub dry_run: bool,
+    /// How many issues to update at the same time, at least one.
+    pub concurrency: usize,
+    /// The name of the workflow transition, or of the status it leads to,
+    /// to move the issues through once they have the version.
+    pub transition: Option<String>
+}
+
+/// The comment added to every issue which gets the version, rendered from the
+/// template with the commits of the range mentioning the issue, see
+/// `comment::render`.
+pub struct ReleaseComment<'m> {
+    pub template: String,
+    pub range: Range,
+    /// The issue keys with the commits mentioning them, as given by
+    /// `TokenParser::find`.
+    pub mentions: &'m [Mentions<'m>]
+}
+
+/// What a release does once the issues have the version, see
+/// `ReleasePlan::run`.
+#[derive(Debug, Clone, Default)]
+pub struct ReleaseOptions {
+    /// Comment on the issues which get the version, with the template read
+    /// from `comment_template`, or the default one.
+    pub comment: bool,
+    pub comment_template: Option<String>,
+    /// Mark the versions as released on this date, as taken by
+    /// `release_date`. `commit` is the date of the last commit of the range
+    /// in the repository at `repo`.
+    pub release_date: Option<String>,
+    pub repo: String,
+    pub description: Option<String>,
+    /// Render the release notes, with links to the issues on this Jira.
+    pub notes_url: Option<String>
+}
+
+/// What `ReleasePlan::run` did.
+pub struct ReleaseRun<'s> {
+    pub result: ReleaseResult<'s>,
+    /// The release notes as Markdown, when asked for and the release could
+    /// be finished.
+    pub notes: Option<String>,
+    /// The error which stopped the release after the issues were updated,
+    /// e.g. when the version couldn't be released.
+    pub error: Option<Error>
+}
+
+/// The version of a project, as found or created by the release.
+pub struct ProjectVersion {
+    pub project_id: String,
+    /// On a dry run, a version which would be created has an empty id.
+    pub version: JiraVersion,
+    /// True when the version had to be created.
+    pub created: bool,
+    /// The date the version was marked as released on, if it was.
+    pub release_date: Option<String>
+}
+
+#[derive(Debug, PartialEq)]
+pub enum IssueStatus {
+    NotFound,
+    AlreadyPresent,
+    /// On a dry run, this means the version would have been added.
+    Added,
+    /// The issue could not be updated, the error is in the report.
+    Failed
+}
+
+impl IssueStatus {
+    /// The name used in reports, e.g. `already-present`.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            IssueStatus::NotFound => "not-found",
+            IssueStatus::AlreadyPresent => "already-present",
+            IssueStatus::Added => "added",
+            IssueStatus::Failed => "error"
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum TransitionStatus {
+    /// On a dry run, this means the issue would have been transitioned.
+    Done,
+    /// The issue already has the status the transition leads to.
+    AlreadyDone,
+    /// The transition can't be done from the current status of the issue.
+    Unavailable,
+    /// Jira refused the transition, e.g. because of a validator. The error
+    /// is in the report, but the issue still has the version.
+    Failed
+}
+
+impl TransitionStatus {
+    /// The name used in reports, e.g. `already-done`.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            TransitionStatus::Done => "done",
+            TransitionStatus::AlreadyDone => "already-done",
+            TransitionStatus::Unavailable => "unavailable",
+            TransitionStatus::Failed => "failed"
+        }
+    }
+}
+
+/// What the release did to an issue.
+pub struct IssueReport<'s> {
+    pub key: &'s str,
+    pub status: IssueStatus,
+    /// The issue as it was before being modified. None when it doesn't
+    /// exist.
+    pub fields: Option<JiraIssueFields>,
+    /// None when no transition was asked for or the issue doesn't exist.
+    pub transition: Option<TransitionStatus>,
+    /// Why the transition failed, when its status is Failed. It doesn't fail
+    /// the issue.
+    pub transition_error: Option<Error>,
+    /// True when the issue was commented on, or would be on a dry run.
+    pub commented: bool,
+    /// Why the issue could not be updated, when its status is Failed.
+    pub error: Option<Error>
+}
+
+pub struct ReleaseResult<'s> {
+    /// One for every project, in the same order as the project ids.
+    pub versions: Vec<ProjectVersion>,
+    /// One for every issue, in the same order as the issue keys.
+    pub issues: Vec<IssueReport<'s>>
+}
+
+impl<'s> ReleaseResult<'s> {
+    pub fn with_status(&self, status: IssueStatus) -> Vec<&'s str> {
+        self.issues
+            .iter()
+            .filter(|issue| issue.status == status)
+            .map(|issue| issue.key)
+            .collect()
+    }
+
+    pub fn with_transition(&self, status: TransitionStatus) -> Vec<&'s str> {
+        self.issues
+            .iter()
+            .filter(|issue| issue.transition.as_ref() == Some(&status))
+            .map(|issue| issue.key)
+            .collect()
+    }
+
+    /// The issues commented on, or which would be on a dry run.
+    pub fn commented(&self) -> Vec<&'s str> {
+        self.issues
+            .iter()
+            .filter(|issue| issue.commented)
+            .map(|issue| issue.key)
+            .collect()
+    }
+
+    /// Takes out the error of the first issue which failed.
+    pub fn take_error(&mut self) -> Option<Error> {
+        self.issues
+            .iter_mut()
+            .filter_map(|issue| issue.error.take())
+            .next()
+    }
+
+    /// The release notes of the issues which exist.
+    pub fn notes(&self) -> Vec<Note> {
+        self.issues
+            .iter()
+            .filter_map(|issue| issue.fields.as_ref().map(|fields| {
+                Note {
+                    key: issue.key.to_owned(),
+                    summary: fields.summary.clone(),
+                    issue_type: fields.issuetype.name.clone(),
+                    status: fields.status.name.clone(),
+                    priority: fields.priority.as_ref().map(|p| p.name.clone())
+                }
+            }))
+            .collect()
+    }
+}
+
+/// The date to release the version on, as YYYY-MM-DD. `date` is either a
+/// date as YYYY-MM-DD, `today`, or `commit` for the date of `revision`.
+pub fn release_date(repo_path: &str, revision: &str, date: &str)
+        -> Result<String, Error> {
+    match date {
+        "today" => Ok(time::now().strftime("%Y-%m-%d").unwrap().to_string()),
+        "commit" => git::commit_date(repo_path, revision),
+        date => Ok(date.to_owned())
+    }
+}
+
+fn failed_report<'s>(issue_token: &'s str, error: Error) -> IssueReport<'s> {
+    IssueReport {
+        key: issue_token,
+        status: IssueStatus::Failed,
+        fields: None,
+        transition: None,
+        transition_error: None,
+        commented: false,
+        error: Some(error)
+    }
+}
+
+// The key of the project the issue belongs to, e.g. "EX" for "EX-1".
+fn project_of(issue_token: &str) -> String {
+    issue_token.rsplitn(2, '-').last().unwrap().to_uppercase()
+}
+
+// Picks the version of the project the issue belongs to. The issue is from
+// one of the projects, publish checks it.
+fn version_for<'v>(versions: &'v [ProjectVersion], issue_token: &str)
+        -> &'v JiraVersion {
+    let project = project_of(issue_token);
+    let found = versions
+        .iter()
+        .find(|v| v.project_id.to_uppercase() == project)
+        .expect("Issue token from a project which wasn't given");
+    &found.version
+}
+
+impl ReleasePlan {
+    /// A plan which isn't a dry run, updates one issue at a time and doesn't
+    /// transition the issues.
+    pub fn new(project_ids: Vec<String>, version_name: &str) -> ReleasePlan {
+        ReleasePlan {
+            project_ids: project_ids,
+            version_name: version_name.to_owned(),
+            dry_run: false,
+            concurrency: 1,
+            transition: None
+        }
+    }
+
+    // Makes a GET request and then creates the jira version if it doesnt
+    // exists. On a dry run the version is never created and a placeholder
+    // without an id is returned.
+    fn ensure_project_version(
+            &self,
+            client: &dyn JiraApi,
+            journal: &Journal,
+            project_id: &str
+            ) -> Result<ProjectVersion, Error> {
+        let found = try!(client.find_version(project_id, &self.version_name));
+        let (version, created) = match found {
+            Some(version) => (version, false),
+            None if self.dry_run => {
+                let placeholder = JiraVersion {
+                    name: self.version_name.clone(),
+                    id: String::new()
+                };
+                (placeholder, true)
+            },
+            None => {
+                let version = try!(
+                    client.create_version(project_id, &self.version_name)
+                );
+                try!(journal.record(
+                    Entry::project(Action::CreateVersion, project_id, &version)
+                ));
+                (version, true)
+            }
+        };
+        Ok(ProjectVersion {
+            project_id: project_id.to_owned(),
+            version: version,
+            created: created,
+            release_date: None
+        })
+    }
+
+    // Moves the issue through the transition with the given name. The name
+    // of the status the transition leads to is accepted as well.
+    fn transition_issue(
+            &self,
+            client: &dyn JiraApi,
+            issue_token: &str,
+            fields: &JiraIssueFields,
+            name: &str
+            ) -> Result<TransitionStatus, Error> {
+        let name = name.to_lowercase();
+        if fields.status.name.to_lowercase() == name {
+            return Ok(TransitionStatus::AlreadyDone);
+        }
+        let transitions = try!(client.transitions(issue_token));
+        let found = transitions.iter().find(|t| {
+            t.name.to_lowercase() == name || t.to.name.to_lowercase() == name
+        });
+        match found {
+            None => Ok(TransitionStatus::Unavailable),
+            Some(transition) => {
+                if !self.dry_run {
+                    try!(client.transition(issue_token, transition));
+                }
+                Ok(TransitionStatus::Done)
+            }
+        }
+    }
+
+    // Comments on the issue which is about to get the version.
+    fn comment_issue(
+            &self,
+            client: &dyn JiraApi,
+            journal: &Journal,
+            issue_token: &str,
+            comment: &ReleaseComment,
+            version: &JiraVersion
+            ) -> Result<(), Error> {
+        let commits = comment.mentions
+            .iter()
+            .find(|m| m.key == issue_token)
+            .map_or(&[][..], |m| &m.commits[..]);
+        let body = comment::render(&comment.template, &self.version_name,
+            &comment.range.to_string(), commits);
+        if !self.dry_run {
+            try!(client.comment(issue_token, &body));
+            try!(journal.record(
+                Entry::issue(Action::Comment, issue_token, version)
+            ));
+        }
+        Ok(())
+    }
+
+    // Adds the version to the issue, whose fields were fetched beforehand.
+    // The issue is commented on before, so that when commenting fails, the
+    // issue doesn't have the version and is commented on when the release
+    // is run again.
+    fn ensure_issue_version<'s>(
+            &self,
+            client: &dyn JiraApi,
+            journal: &Journal,
+            issue_token: &'s str,
+            fields: Option<JiraIssueFields>,
+            version: &JiraVersion,
+            comment: Option<&ReleaseComment>
+            ) -> Result<IssueReport<'s>, Error> {
+        let status = match fields {
+            None => IssueStatus::NotFound,
+            Some(ref fields) => {
+                let versions = &fields.fixVersions;
+                if versions.iter().any(|v| v.name == self.version_name) {
+                    IssueStatus::AlreadyPresent
+                } else {
+                    if let Some(comment) = comment {
+                        try!(self.comment_issue(client, journal, issue_token,
+                            comment, version));
+                    }
+                    if !self.dry_run {
+                        try!(client.add_issue_version(issue_token, version));
+                        try!(journal.record(
+                            Entry::issue(Action::AddFixVersion, issue_token,
+                                version)
+                        ));
+                    }
+                    IssueStatus::Added
+                }
+            }
+        };
+        // The issue has the version whatever happens to the transition.
+        let (transition, transition_error) =
+            match (&fields, &self.transition) {
+                (&Some(ref fields), &Some(ref name)) => {
+                    match self.transition_issue(client, issue_token, fields,
+                            name) {
+                        Ok(done) => (Some(done), None),
+                        Err(e) => (Some(TransitionStatus::Failed), Some(e))
+                    }
+                },
+                _ => (None, None)
+            };
+        if transition == Some(TransitionStatus::Done) && !self.dry_run {
+            try!(journal.record(
+                Entry::issue(Action::Transition, issue_token, version)
+            ));
+        }
+        let commented = status == IssueStatus::Added && comment.is_some();
+        Ok(IssueReport {
+            key: issue_token,
+            status: status,
+            fields: fields,
+            transition: transition,
+            transition_error: transition_error,
+            commented: commented,
+            error: None
+        })
+    }
+
+    // Runs ensure_issue_version on a pool of self.concurrency threads. The
+    // reports are in the same order as the tokens. An issue which fails
+    // doesn't stop the others, its report has the Failed status and the
+    // error. `fetched` has the fields of the issues found by the searches,
+    // or the error of their search, in which case they are reported as
+    // failed.
+    fn ensure_issue_versions<'s>(
+            &self,
+            client: &dyn JiraApi,
+            journal: &Journal,
+            issue_tokens: &'s [String],
+            fetched: &HashMap<String, Result<JiraIssueFields, Error>>,
+            versions: &[ProjectVersion],
+            comment: Option<&ReleaseComment>
+            ) -> Vec<IssueReport<'s>> {
+        let next = AtomicUsize::new(0);
+        let results: Mutex<Vec<Option<IssueReport<'s>>>> = Mutex::new(
+            issue_tokens
+                .iter()
+                .map(|key| match fetched.get(key) {
+                    Some(&Err(ref e)) =>
+                        Some(failed_report(key, e.duplicate())),
+                    _ => None
+                })
+                .collect()
+        );
+        let workers = cmp::max(1, cmp::min(self.concurrency,
+            issue_tokens.len()));
+        thread::scope(|scope| {
+            for _ in 0..workers {
+                scope.spawn(|| {
+                    loop {
+                        let i = next.fetch_add(1, Ordering::SeqCst);
+                        if i >= issue_tokens.len() {
+                            break;
+                        }
+                        if results.lock().unwrap()[i].is_some() {
+                            continue;
+                        }
+                        let issue_token = &issue_tokens[i];
+                        let version = version_for(versions, issue_token);
+                        let fields = match fetched.get(issue_token) {
+                            Some(&Ok(ref fields)) => Some(fields.clone()),
+                            _ => None
+                        };
+                        let report = self.ensure_issue_version(client,
+                            journal, issue_token, fields, version, comment
+                        ).unwrap_or_else(|e| failed_report(issue_token, e));
+                        results.lock().unwrap()[i] = Some(report);
+                    }
+                });
+            }
+        });
+        results
+            .into_inner()
+            .unwrap()
+            .into_iter()
+            .map(Option::unwrap)
+            .collect()
+    }
+
+    /// Creates the version in every project if needed and adds it to every
+    /// issue, commenting on the issues which get it when a comment is given.
+    /// Every change made is recorded in the journal. The issue keys should
+    /// be unique, as given by `TokenParser::find`, and from the projects of
+    /// the plan, whose keys must be valid, see
+    /// `token_parser::validate_project_keys`. Otherwise nothing is done and a
+    /// config error is returned.
+    pub fn publish<'s>(
+            &self,
+            client: &dyn JiraApi,
+            journal: &Journal,
+            issue_tokens: &'s [String],
+            comment: Option<&ReleaseComment>
+            ) -> Result<ReleaseResult<'s>, Error> {
+        try!(token_parser::validate_project_keys(&self.project_ids));
+        let foreign = issue_tokens.iter().find(|key| {
+            let project = project_of(key);
+            !self.project_ids.iter().any(|p| p.to_uppercase() == project)
+        });
+        if let Some(key) = foreign {
+            return Err(Error::Config(format!("Issue {} isn't from the \
+                projects released: {}", key, self.project_ids.join(", "))));
+        }
+        let mut versions: Vec<ProjectVersion> = Vec::new();
+        for project_id in &self.project_ids {
+            versions.push(
+                try!(self.ensure_project_version(client, journal, project_id))
+            );
+        }
+        // The issues are fetched by chunks, those missing don't exist and
+        // will be reported as not found. When the search of a chunk fails,
+        // only its issues fail.
+        let mut fetched = HashMap::new();
+        for chunk in issue_tokens.chunks(SEARCH_CHUNK) {
+            match client.search_issues(chunk) {
+                Ok(found) => fetched.extend(
+                    found.into_iter().map(|(key, fields)| (key, Ok(fields)))
+                ),
+                Err(e) => for key in chunk {
+                    fetched.insert(key.clone(), Err(e.duplicate()));
+                }
+            }
+        }
+        let issues =
+            self.ensure_issue_versions(client, journal, issue_tokens,
+                &fetched, &versions, comment);
+
+        Ok(ReleaseResult {
+            versions: versions,
+            issues: issues
+        })
+    }
+
+    /// Marks the version of every project as released on the date, given as
+    /// YYYY-MM-DD. On a dry run, only the date is set on the versions.
+    pub fn release(
+            &self,
+            client: &dyn JiraApi,
+            journal: &Journal,
+            result: &mut ReleaseResult,
+            release_date: &str,
+            description: Option<&str>
+            ) -> Result<(), Error> {
+        for project in &mut result.versions {
+            if !self.dry_run {
+                try!(client.release_version(&project.version, release_date,
+                    description));
+                try!(journal.record(Entry::project(Action::ReleaseVersion,
+                    &project.project_id, &project.version)));
+            }
+            project.release_date = Some(release_date.to_owned());
+        }
+        Ok(())
+    }
+
+
+    // The steps after every issue got the version: the versions are
+    // released, then the notes are rendered.
+    fn finish(
+            &self,
+            client: &dyn JiraApi,
+            journal: &Journal,
+            result: &mut ReleaseResult,
+            range: &Range,
+            options: &ReleaseOptions
+            ) -> Result<Option<String>, Error> {
+        if let Some(ref date) = options.release_date {
+            let date = try!(release_date(&options.repo, &range.to, date));
+            let description = options.description.as_ref().map(|d| &d[..]);
+            try!(self.release(client, journal, result, &date, description));
+        }
+        Ok(options.notes_url.as_ref().map(|url| {
+            release_notes::render(&self.version_name, url, &result.notes())
+        }))
+    }
+
+    /// Publishes the version to the issues mentioned in the range, commenting
+    /// on them if asked to. The release is only finished, by releasing the
+    /// versions and rendering the notes, when every issue could be updated.
+    /// Once the issues are updated, the result is returned even if finishing
+    /// fails, with the error.
+    pub fn run<'s>(
+            &self,
+            client: &dyn JiraApi,
+            journal: &Journal,
+            issue_tokens: &'s [String],
+            mentions: &[Mentions],
+            range: &Range,
+            options: &ReleaseOptions
+            ) -> Result<ReleaseRun<'s>, Error> {
+        let comment = if options.comment {
+            let path = options.comment_template.as_ref().map(|p| &p[..]);
+            Some(ReleaseComment {
+                template: try!(comment::read_template(path)),
+                range: range.clone(),
+                mentions: mentions
+            })
+        } else {
+            None
+        };
+        let mut result =
+            try!(self.publish(client, journal, issue_tokens, comment.as_ref()));
+        let (notes, error) =
+            if result.with_status(IssueStatus::Failed).is_empty() {
+                match self.finish(client, journal, &mut result, range,
+                        options) {
+                    Ok(notes) => (notes, None),
+                    Err(e) => (None, Some(e))
+                }
+            } else {
+                (None, None)
+            };
+        Ok(ReleaseRun {
+            result: result,
+            notes: notes,
+            error: error
+        })
+    }
+}
+
+
+#[cfg(test)]
+fn fake_jira() -> ::fake::FakeJira {
+    let jira = ::fake::FakeJira::new();
+    jira.add_issue("EX-1", "First");
+    jira.add_issue("EX-3", "Third");
+    jira
+}
+
+#[test]
+fn published() {
+    let jira = fake_jira();
+    let released = jira.add_version("EX", "1.1.0");
+    let mut fields = jira.issue("EX-3").unwrap();
+    fields.fixVersions.push(released);
+    jira.add_issue_fields("EX-3", fields);
+    jira.add_version("EX", "1.2.0");
+    let mut plan = ReleasePlan::new(vec![String::from("EX")], "1.2.0");
+    plan.concurrency = 2;
+    let tokens = [String::from("EX-1"), String::from("EX-2"),
+        String::from("EX-3")];
+    let result =
+        plan.publish(&jira, &Journal::disabled(), &tokens, None).unwrap();
+
+    assert!(!result.versions[0].created);
+    assert_eq!(result.with_status(IssueStatus::Added), ["EX-1", "EX-3"]);
+    assert_eq!(result.with_status(IssueStatus::NotFound), ["EX-2"]);
+    let names = |key| -> Vec<String> {
+        jira.issue(key).unwrap().fixVersions
+            .into_iter()
+            .map(|v| v.name)
+            .collect()
+    };
+    assert_eq!(names("EX-1"), ["1.2.0"]);
+    assert_eq!(names("EX-3"), ["1.1.0", "1.2.0"]);
+
+    let result =
+        plan.publish(&jira, &Journal::disabled(), &tokens, None).unwrap();
+    assert_eq!(result.with_status(IssueStatus::AlreadyPresent),
+        ["EX-1", "EX-3"]);
+    assert_eq!(jira.versions("EX").len(), 2);
+}
+
+#[test]
+fn dry_run() {
+    let jira = fake_jira();
+    let mut plan = ReleasePlan::new(vec![String::from("EX")], "1.2.0");
+    plan.dry_run = true;
+    plan.transition = Some(String::from("Done"));
+    jira.add_transition("EX-1", "Close", "Done");
+    let tokens = [String::from("EX-1")];
+    let mut result =
+        plan.publish(&jira, &Journal::disabled(), &tokens, None).unwrap();
+    plan.release(&jira, &Journal::disabled(), &mut result, "2024-01-01",
+        None).unwrap();
+
+    assert!(result.versions[0].created);
+    assert_eq!(result.versions[0].version.id, "");
+    assert_eq!(result.with_status(IssueStatus::Added), ["EX-1"]);
+    assert_eq!(result.with_transition(TransitionStatus::Done), ["EX-1"]);
+    assert!(jira.versions("EX").is_empty());
+    let fields = jira.issue("EX-1").unwrap();
+    assert!(fields.fixVersions.is_empty());
+    assert_eq!(fields.status.name, "To Do");
+}
+
+#[test]
+fn failed_issue() {
+    let jira = fake_jira();
+    jira.fail_issue("EX-1", ::hyper::status::StatusCode::InternalServerError);
+    let plan = ReleasePlan::new(vec![String::from("EX")], "1.2.0");
+    let tokens = [String::from("EX-1"), String::from("EX-3")];
+    let mut result =
+        plan.publish(&jira, &Journal::disabled(), &tokens, None).unwrap();
+
+    assert_eq!(result.with_status(IssueStatus::Failed), ["EX-1"]);
+    assert_eq!(result.with_status(IssueStatus::Added), ["EX-3"]);
+    assert_eq!(result.take_error().unwrap().exit_code(), 4);
+    assert!(result.take_error().is_none());
+}
+
+#[test]
+fn foreign_issues() {
+    let jira = fake_jira();
+    let mut plan = ReleasePlan::new(vec![String::from("EX")], "1.2.0");
+    plan.concurrency = 0;
+    let tokens = [String::from("EX-1"), String::from("OTHER-1")];
+    let error = plan.publish(&jira, &Journal::disabled(), &tokens, None).err();
+    assert_eq!(error.unwrap().exit_code(), 2);
+    assert!(jira.versions("EX").is_empty());
+
+    let result =
+        plan.publish(&jira, &Journal::disabled(), &tokens[..1], None).unwrap();
+    assert_eq!(result.with_status(IssueStatus::Added), ["EX-1"]);
+}
+
+#[test]
+fn invalid_projects() {
+    let jira = fake_jira();
+    for projects in &[vec![], vec![String::from("EX/../1")]] {
+        let plan = ReleasePlan::new(projects.clone(), "1.2.0");
+        let error = plan.publish(&jira, &Journal::disabled(), &[], None).err();
+        assert_eq!(error.unwrap().exit_code(), 2);
+    }
+    assert!(jira.versions("EX/../1").is_empty());
+}
+
+#[test]
+fn failed_transition() {
+    use hyper::status::StatusCode;
+
+    let jira = fake_jira();
+    jira.add_transition("EX-1", "Close", "Done");
+    jira.add_transition("EX-3", "Close", "Done");
+    jira.fail_transition("EX-1", StatusCode::BadRequest);
+    let mut plan = ReleasePlan::new(vec![String::from("EX")], "1.2.0");
+    plan.transition = Some(String::from("Close"));
+    let tokens = [String::from("EX-1"), String::from("EX-3")];
+    let mut result = plan.publish(&jira, &Journal::disabled(), &tokens, None)
+        .unwrap();
+    assert_eq!(result.with_status(IssueStatus::Added), ["EX-1", "EX-3"]);
+    assert_eq!(result.with_transition(TransitionStatus::Failed), ["EX-1"]);
+    assert_eq!(result.with_transition(TransitionStatus::Done), ["EX-3"]);
+    let failed = &result.issues[0];
+    assert!(failed.fields.is_some());
+    assert_eq!(failed.transition_error.as_ref().unwrap().exit_code(), 4);
+    assert_eq!(jira.issue("EX-1").unwrap().fixVersions.len(), 1);
+    assert_eq!(jira.issue("EX-1").unwrap().status.name, "To Do");
+    assert_eq!(result.notes().len(), 2);
+    assert!(result.take_error().is_none());
+}
+
+#[test]
+fn failed_search() {
+    use hyper::status::StatusCode;
+
+    let jira = fake_jira();
+    let tokens: Vec<String> = (1..SEARCH_CHUNK + 11)
+        .map(|i| format!("EX-{}", i))
+        .collect();
+    for key in &tokens[3..] {
+        jira.add_issue(key, "More");
+    }
+    let last = &tokens[SEARCH_CHUNK + 5];
+    jira.fail_search(last, StatusCode::InternalServerError);
+    let plan = ReleasePlan::new(vec![String::from("EX")], "1.2.0");
+    let mut result = plan.publish(&jira, &Journal::disabled(), &tokens, None)
+        .unwrap();
+    assert_eq!(result.with_status(IssueStatus::Added).len(), SEARCH_CHUNK - 1);
+    assert_eq!(result.with_status(IssueStatus::NotFound), ["EX-2"]);
+    let failed = result.with_status(IssueStatus::Failed);
+    assert_eq!(failed.len(), 10);
+    assert_eq!(failed[0], tokens[SEARCH_CHUNK]);
+    assert!(jira.issue(last).unwrap().fixVersions.is_empty());
+    assert_eq!(result.take_error().unwrap().exit_code(), 4);
+    assert_eq!(result.take_error().unwrap().exit_code(), 4);
+}
+
+#[test]
+fn release_dates() {
+    assert_eq!(release_date(".", "HEAD", "2016-07-02").unwrap(), "2016-07-02");
+    assert_eq!(release_date(".", "HEAD", "today").unwrap().len(), 10);
+}
+
+#[cfg(test)]
+fn test_comment<'m>(mentions: &'m [Mentions<'m>]) -> ReleaseComment<'m> {
+    ReleaseComment {
+        template: String::from("{version}: {commits}"),
+        range: Range {
+            from: Some(String::from("master")),
+            to: String::from("develop")
+        },
+        mentions: mentions
+    }
+}
+
+#[test]
+fn transitioned_commented_and_released() {
+    let jira = fake_jira();
+    jira.add_transition("EX-1", "Close", "Done");
+    jira.add_transition("EX-3", "Start", "In Progress");
+    let mut plan = ReleasePlan::new(vec![String::from("EX")], "1.2.0");
+    plan.transition = Some(String::from("done"));
+    let tokens = [String::from("EX-1"), String::from("EX-3")];
+    let journal = Journal::disabled();
+    let commit = git::test_commit("0123456789abcdef", "[EX-1] first", "");
+    let mentions = [Mentions {
+        key: String::from("EX-1"),
+        commits: vec![&commit],
+        found_in: vec![::token_parser::Location::Subject]
+    }];
+    let comment = test_comment(&mentions);
+    let mut result =
+        plan.publish(&jira, &journal, &tokens, Some(&comment)).unwrap();
+    assert_eq!(result.with_transition(TransitionStatus::Done), ["EX-1"]);
+    assert_eq!(result.with_transition(TransitionStatus::Unavailable),
+        ["EX-3"]);
+    assert_eq!(jira.issue("EX-1").unwrap().status.name, "Done");
+    assert_eq!(result.commented(), ["EX-1", "EX-3"]);
+    assert_eq!(jira.comments("EX-1"), ["1.2.0: * 0123456 [EX-1] first"]);
+    assert_eq!(jira.comments("EX-3"), ["1.2.0: "]);
+
+    plan.release(&jira, &journal, &mut result, "2024-01-01", None).unwrap();
+    let version = &result.versions[0].version;
+    assert_eq!(jira.release_date(version).unwrap(), "2024-01-01");
+}
+
+#[test]
+fn commented_when_run_again() {
+    let jira = fake_jira();
+    jira.add_issue("EX-2", "Second");
+    jira.fail_issue("EX-2", ::hyper::status::StatusCode::InternalServerError);
+    let plan = ReleasePlan::new(vec![String::from("EX")], "1.2.0");
+    let tokens = [String::from("EX-1"), String::from("EX-2")];
+    let mentions = [];
+    let comment = test_comment(&mentions);
+    let result = plan.publish(&jira, &Journal::disabled(), &tokens,
+        Some(&comment)).unwrap();
+    assert_eq!(result.with_status(IssueStatus::Failed), ["EX-2"]);
+    assert_eq!(result.commented(), ["EX-1"]);
+    assert!(jira.issue("EX-2").unwrap().fixVersions.is_empty());
+
+    // The issue which already had the version isn't commented on again.
+    jira.heal_issue("EX-2");
+    let result = plan.publish(&jira, &Journal::disabled(), &tokens,
+        Some(&comment)).unwrap();
+    assert_eq!(result.with_status(IssueStatus::AlreadyPresent), ["EX-1"]);
+    assert_eq!(result.commented(), ["EX-2"]);
+    assert_eq!(jira.comments("EX-1"), ["1.2.0: "]);
+    assert_eq!(jira.comments("EX-2"), ["1.2.0: "]);
+}
+
+#[test]
+fn finished_without_failures() {
+    let jira = fake_jira();
+    jira.add_issue("EX-2", "Second");
+    jira.fail_issue("EX-2", ::hyper::status::StatusCode::InternalServerError);
+    let plan = ReleasePlan::new(vec![String::from("EX")], "1.2.0");
+    let tokens = [String::from("EX-1"), String::from("EX-2")];
+    let range = test_comment(&[]).range;
+    let options = ReleaseOptions {
+        comment: true,
+        release_date: Some(String::from("2024-01-01")),
+        notes_url: Some(String::from("https://jira.example.com")),
+        ..ReleaseOptions::default()
+    };
+    let run = plan.run(&jira, &Journal::disabled(), &tokens, &[], &range,
+        &options).unwrap();
+    assert_eq!(run.result.commented(), ["EX-1"]);
+    assert!(run.result.versions[0].release_date.is_none());
+    assert!(run.notes.is_none());
+    assert!(run.error.is_none());
+
+    jira.heal_issue("EX-2");
+    let run = plan.run(&jira, &Journal::disabled(), &tokens, &[], &range,
+        &options).unwrap();
+    assert_eq!(run.result.commented(), ["EX-2"]);
+    let version = &run.result.versions[0].version;
+    assert_eq!(jira.release_date(version).unwrap(), "2024-01-01");
+    assert!(run.notes.unwrap().contains("EX-2"));
+
+    let options = ReleaseOptions {
+        comment: true,
+        comment_template: Some(String::from("/nonexistent/template")),
+        ..ReleaseOptions::default()
+    };
+    let error = plan.run(&jira, &Journal::disabled(), &tokens, &[], &range,
+        &options).err();
+    assert_eq!(error.unwrap().exit_code(), 8);
+}
@@ -0,0 +1,96 @@
+//! Renders the issues of a release as Markdown release notes. Issues are
+//! grouped in one section per issue type, sorted by name, and keep the order
+//! they were found in the commit logs within a section.
+
+use std::collections::BTreeMap;
+
+pub struct Note {
+    pub key: String,
+    pub summary: String,
+    pub issue_type: String,
+    pub status: String,
+    pub priority: Option<String>
+}
+
+// Escapes the characters which would otherwise be taken as Markdown
+// formatting in an issue summary.
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\\' | '`' | '*' | '_' | '[' | ']' | '<' | '>' | '#' => {
+                escaped.push('\\');
+                escaped.push(c);
+            },
+            _ => escaped.push(c)
+        }
+    }
+    escaped
+}
+
+/// Renders the notes as a Markdown document, with links to the issues on
+/// Jira.
+pub fn render(version_name: &str, jira_url: &str, notes: &[Note]) -> String {
+    let mut sections: BTreeMap<&str, Vec<&Note>> = BTreeMap::new();
+    for note in notes {
+        sections.entry(&note.issue_type).or_insert_with(Vec::new).push(note);
+    }
+
+    let mut markdown = format!("# Release {}\n", version_name);
+    if notes.is_empty() {
+        markdown.push_str("\nNo issues in this release.\n");
+    }
+    for (issue_type, section) in sections {
+        markdown.push_str(&format!("\n## {}\n\n", issue_type));
+        for note in section {
+            let details = match note.priority {
+                Some(ref priority) => format!("{}, {}", note.status, priority),
+                None => note.status.clone()
+            };
+            markdown.push_str(&format!(
+                "- [{key}]({url}/browse/{key}) {summary} ({details})\n",
+                key = note.key,
+                url = jira_url.trim_end_matches('/'),
+                summary = escape(&note.summary),
+                details = details
+            ));
+        }
+    }
+    markdown
+}
+
+#[test]
+fn grouped_by_type() {
+    let note = |key: &str, issue_type: &str, priority: Option<&str>| Note {
+        key: key.to_owned(),
+        summary: format!("Summary of {}", key),
+        issue_type: issue_type.to_owned(),
+        status: "Done".to_owned(),
+        priority: priority.map(String::from)
+    };
+    let notes = vec![
+        note("EX-3", "Story", Some("Medium")),
+        note("EX-1", "Bug", Some("High")),
+        note("EX-2", "Story", None)
+    ];
+    let markdown = render("1.2.0", "http://jira.com/", &notes);
+    assert_eq!(markdown, "# Release 1.2.0
+
+## Bug
+
+- [EX-1](http://jira.com/browse/EX-1) Summary of EX-1 (Done, High)
+
+## Story
+
+- [EX-3](http://jira.com/browse/EX-3) Summary of EX-3 (Done, Medium)
+- [EX-2](http://jira.com/browse/EX-2) Summary of EX-2 (Done)
+");
+}
+
+#[test]
+fn escaped_summary() {
+    assert_eq!(escape("Fix *all* the [things]"),
+        "Fix \\*all\\* the \\[things\\]");
+    assert_eq!(render("1.0", "http://jira.com", &[]),
+        "# Release 1.0\n\nNo issues in this release.\n");
+}
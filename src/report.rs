@@ -0,0 +1,222 @@
+//! The report printed with `--output json`, a single document describing the
+//! outcome of a run so that scripts don't have to parse the text output. It is
+//! printed even when the run fails, with the error which ended it.
+
+use error::Error;
+use git::Range;
+use rustc_serialize::json;
+use release::ReleaseResult;
+use token_parser::Mentions;
+
+#[derive(RustcEncodable)]
+struct CommitReport {
+    sha: String,
+    subject: String,
+    author: String,
+    date: String
+}
+
+#[derive(RustcEncodable)]
+struct TokenReport {
+    key: String,
+    // Where the key was found: subject, body or trailer.
+    found_in: Vec<String>,
+    commits: Vec<CommitReport>
+}
+
+#[derive(RustcEncodable)]
+struct VersionReport {
+    project_id: String,
+    id: String,
+    name: String,
+    created: bool,
+    release_date: Option<String>
+}
+
+#[derive(RustcEncodable)]
+struct IssueReport {
+    key: String,
+    // One of added, already-present, not-found or error.
+    status: String,
+    transition: Option<String>,
+    // Why the transition failed, which doesn't fail the issue.
+    transition_error: Option<String>,
+    commented: bool,
+    error: Option<String>
+}
+
+#[derive(RustcEncodable)]
+struct Report {
+    dry_run: bool,
+    // None when the run failed before the range was known.
+    range: Option<Range>,
+    tokens: Vec<TokenReport>,
+    versions: Vec<VersionReport>,
+    issues: Vec<IssueReport>,
+    // The error which ended the run, other than those of the issues.
+    error: Option<String>
+}
+
+fn token_report(mentions: &Mentions) -> TokenReport {
+    TokenReport {
+        key: mentions.key.clone(),
+        found_in: mentions.found_in
+            .iter()
+            .map(|location| location.name().to_owned())
+            .collect(),
+        commits: mentions.commits.iter().map(|commit| CommitReport {
+            sha: commit.sha.clone(),
+            subject: commit.summary.clone(),
+            author: commit.author.clone(),
+            date: commit.date.clone()
+        }).collect()
+    }
+}
+
+/// Renders the report as pretty printed JSON. `error` is the one which stopped
+/// the run after the issues were updated, e.g. when releasing the version.
+pub fn render(dry_run: bool, range: &Range, tokens: &[Mentions],
+        result: &ReleaseResult, error: Option<&Error>) -> String {
+    let report = Report {
+        dry_run: dry_run,
+        range: Some(range.clone()),
+        tokens: tokens.iter().map(token_report).collect(),
+        versions: result.versions.iter().map(|project| VersionReport {
+            project_id: project.project_id.clone(),
+            id: project.version.id.clone(),
+            name: project.version.name.clone(),
+            created: project.created,
+            release_date: project.release_date.clone()
+        }).collect(),
+        issues: result.issues.iter().map(|issue| IssueReport {
+            key: issue.key.to_owned(),
+            status: issue.status.name().to_owned(),
+            transition: issue.transition.as_ref()
+                .map(|t| t.name().to_owned()),
+            transition_error: issue.transition_error.as_ref()
+                .map(|e| e.to_string()),
+            commented: issue.commented,
+            error: issue.error.as_ref().map(|e| e.to_string())
+        }).collect(),
+        error: error.map(|e| e.to_string())
+    };
+    json::as_pretty_json(&report).to_string()
+}
+
+/// Renders the report of a run which failed before any issue was updated,
+/// with only the error and the range if it was known.
+pub fn render_error(dry_run: bool, range: Option<&Range>, error: &Error)
+        -> String {
+    let report = Report {
+        dry_run: dry_run,
+        range: range.cloned(),
+        tokens: Vec::new(),
+        versions: Vec::new(),
+        issues: Vec::new(),
+        error: Some(error.to_string())
+    };
+    json::as_pretty_json(&report).to_string()
+}
+
+#[test]
+fn json_report() {
+    use error::Error;
+    use jira::JiraVersion;
+    use release::{IssueStatus, ProjectVersion, TransitionStatus};
+    use release::IssueReport as Issue;
+
+    let range = Range {
+        from: Some(String::from("master")),
+        to: String::from("develop")
+    };
+    use token_parser::Location;
+    let commit = ::git::test_commit("0123456789abcdef", "[EX-1] first",
+        "Refs: EX-2");
+    let tokens = [
+        Mentions {
+            key: String::from("EX-1"),
+            commits: vec![&commit],
+            found_in: vec![Location::Subject, Location::Body]
+        },
+        Mentions {
+            key: String::from("EX-2"),
+            commits: vec![&commit],
+            found_in: vec![Location::Trailer]
+        }
+    ];
+    let issue = |key, status, transition, error| Issue {
+        key: key,
+        status: status,
+        fields: None,
+        transition: transition,
+        transition_error: None,
+        commented: false,
+        error: error
+    };
+    let result = ReleaseResult {
+        versions: vec![ProjectVersion {
+            project_id: String::from("EX"),
+            version: JiraVersion {
+                name: String::from("1.2.0"),
+                id: String::from("10")
+            },
+            created: true,
+            release_date: None
+        }],
+        issues: vec![
+            issue("EX-1", IssueStatus::Added, Some(TransitionStatus::Done),
+                None),
+            issue("EX-2", IssueStatus::Failed, None,
+                Some(Error::Config(String::from("Broken"))))
+        ]
+    };
+    let released = Error::Config(String::from("Not released"));
+    let report = json::Json::from_str(&render(false, &range, &tokens,
+        &result, Some(&released))).unwrap();
+    let expected = json::Json::from_str(r#"{
+        "dry_run": false,
+        "range": {"from": "master", "to": "develop"},
+        "tokens": [
+            {"key": "EX-1", "found_in": ["subject", "body"], "commits": [{
+                "sha": "0123456789abcdef",
+                "subject": "[EX-1] first",
+                "author": "Tester",
+                "date": "2016-07-02"
+            }]},
+            {"key": "EX-2", "found_in": ["trailer"], "commits": [{
+                "sha": "0123456789abcdef",
+                "subject": "[EX-1] first",
+                "author": "Tester",
+                "date": "2016-07-02"
+            }]}
+        ],
+        "versions": [{
+            "project_id": "EX",
+            "id": "10",
+            "name": "1.2.0",
+            "created": true,
+            "release_date": null
+        }],
+        "issues": [
+            {"key": "EX-1", "status": "added", "transition": "done",
+                "transition_error": null, "commented": false, "error": null},
+            {"key": "EX-2", "status": "error", "transition": null,
+                "transition_error": null, "commented": false,
+                "error": "Broken"}
+        ],
+        "error": "Not released"
+    }"#).unwrap();
+    assert_eq!(report, expected);
+
+    let failed = json::Json::from_str(&render_error(true, None,
+        &Error::Config(String::from("Broken")))).unwrap();
+    let expected = json::Json::from_str(r#"{
+        "dry_run": true,
+        "range": null,
+        "tokens": [],
+        "versions": [],
+        "issues": [],
+        "error": "Broken"
+    }"#).unwrap();
+    assert_eq!(failed, expected);
+}
@@ -1,13 +1,105 @@
 extern crate regex;
 
+use error::Error;
+use git::Commit;
 use regex::Regex;
 
 struct ComplexPattern {
     predicate_str: String,
     splitter_str: String,
-    project_id: String,
+    key: Regex,
     predicate: Regex,
-    splitter: Regex
+    // None when every key in the inner group is taken.
+    splitter: Option<Regex>
+}
+
+/// A pattern finding issue keys at the start of commit subjects, in addition
+/// to the built-in ones.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TokenPattern {
+    /// Keys between two tags, e.g. `feat(FOO-1): ...` with `feat\(` and
+    /// `\):`. Every field is a regex; `separator` is between the project and
+    /// the number of a key, `multi_separator` between the keys.
+    Tags {
+        open_tag: String,
+        close_tag: String,
+        separator: String,
+        multi_separator: String
+    },
+    /// A regex with a group named `inner`, in which every key of the projects
+    /// is taken, e.g. `^(?P<inner>[A-Z]+-[0-9]+(/[A-Z]+-[0-9]+)*) `.
+    Regex(String)
+}
+
+/// Where an issue key was found in a commit message.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Location {
+    Subject,
+    /// A line of the body starting with the key, like a subject would.
+    Body,
+    /// A trailer such as `Refs: FOO-1, FOO-2` or `Jira: FOO-1`.
+    Trailer
+}
+
+impl Location {
+    /// The name used in reports, e.g. `subject`.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Location::Subject => "subject",
+            Location::Body => "body",
+            Location::Trailer => "trailer"
+        }
+    }
+}
+
+/// An issue key found in a commit.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Token {
+    pub key: String,
+    pub location: Location
+}
+
+/// An issue key with the commits mentioning it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mentions<'c> {
+    pub key: String,
+    /// In the order of the commits, each commit once.
+    pub commits: Vec<&'c Commit>,
+    /// Where the key was found in those commits, each location once.
+    pub found_in: Vec<Location>
+}
+
+// The trailers of a commit message listing issue keys.
+const TRAILERS: &'static str = "^(?i)[ ]*(?:refs|jira)[ ]*:(?P<keys>.*)$";
+
+/// Checks that the project key follows the rules of Jira: an uppercase letter
+/// followed by uppercase letters, digits or underscores, e.g. `FOO_2`.
+pub fn validate_project_key(key: &str) -> Result<(), Error> {
+    let valid = Regex::new("^[A-Z][A-Z0-9_]+$").unwrap();
+    if valid.is_match(key) {
+        Ok(())
+    } else {
+        Err(Error::Config(format!("Invalid project key {}: it must start \
+            with an uppercase letter followed by uppercase letters, digits \
+            or underscores", key)))
+    }
+}
+
+/// Checks that there is at least one project key and that every one is valid,
+/// see `validate_project_key`.
+pub fn validate_project_keys(keys: &[String]) -> Result<(), Error> {
+    if keys.is_empty() {
+        return Err(Error::Config(String::from("At least one project key is \
+            required")));
+    }
+    for key in keys {
+        try!(validate_project_key(key));
+    }
+    Ok(())
+}
+
+fn invalid_pattern(pattern: &str, e: regex::Error) -> Error {
+    Error::Config(format!("Invalid token pattern {}: {}", pattern, e))
 }
 
 type ComplexMatch = Vec<String>;
@@ -45,53 +137,107 @@ impl ComplexPattern {
         predicate_str
     }
 
-    pub fn new(project_id: &str,
+    // Matches any of the project ids, e.g. "(?:api|web)". The ids are
+    // escaped, so they can't make the patterns invalid.
+    fn projects(project_ids: &[String]) -> String {
+        let mut projects = String::from("(?:");
+        for (i, project_id) in project_ids.iter().enumerate() {
+            if i != 0 {
+                projects.push('|');
+            }
+            projects.push_str(&regex::quote(project_id));
+        }
+        projects.push(')');
+        projects
+    }
+
+    fn key(project_ids: &[String], tag_seperator: &str)
+            -> Result<Regex, Error> {
+        let key_str = String::from("(?i)(?P<project>") +
+            &ComplexPattern::projects(project_ids) + ")" + tag_seperator +
+            "(?P<number>[0-9]+)";
+        Regex::new(&key_str).map_err(|e| invalid_pattern(tag_seperator, e))
+    }
+
+    pub fn new(project_ids: &[String],
             tag_seperator: &str,
             open_tag: &str,
             close_tag: &str,
             multi_separator: &str) -> ComplexPattern {
+        // Only used with the built-in tags, which are valid regexes.
+        ComplexPattern::with_tags(project_ids, tag_seperator, open_tag,
+            close_tag, multi_separator).unwrap()
+    }
 
-        // TODO: Sanitize user input: If the project id is a bad regex
-        // currently it can cause the program to crash. I want a clean error
-        // message instead.
-        let pred_str = ComplexPattern::predicate(project_id,
+    pub fn with_tags(project_ids: &[String],
+            tag_seperator: &str,
+            open_tag: &str,
+            close_tag: &str,
+            multi_separator: &str) -> Result<ComplexPattern, Error> {
+        let projects = ComplexPattern::projects(project_ids);
+        let pred_str = ComplexPattern::predicate(&projects,
             tag_seperator,
             open_tag,
             close_tag,
             multi_separator);
-        let pred_reg = Regex::new(&pred_str).unwrap();
+        let pred_reg = try!(
+            Regex::new(&pred_str).map_err(|e| invalid_pattern(&pred_str, e))
+        );
         let spl_str = multi_separator.to_owned();
-        let spl_reg = Regex::new(&spl_str).unwrap();
+        let spl_reg = try!(
+            Regex::new(&spl_str).map_err(|e| invalid_pattern(&spl_str, e))
+        );
 
-        ComplexPattern {
-            project_id: project_id.to_uppercase(),
+        Ok(ComplexPattern {
+            key: try!(ComplexPattern::key(project_ids, tag_seperator)),
             predicate_str: pred_str,
             splitter_str: spl_str,
             predicate: pred_reg,
-            splitter: spl_reg
+            splitter: Some(spl_reg)
+        })
+    }
+
+    // The keys are taken from the inner group of the regex given by the
+    // user.
+    pub fn with_regex(project_ids: &[String], predicate: &str)
+            -> Result<ComplexPattern, Error> {
+        let pred_reg = try!(
+            Regex::new(predicate).map_err(|e| invalid_pattern(predicate, e))
+        );
+        if !pred_reg.capture_names().any(|name| name == Some("inner")) {
+            return Err(Error::Config(format!("Token pattern {} has no group \
+                named inner", predicate)));
         }
+        Ok(ComplexPattern {
+            key: try!(ComplexPattern::key(project_ids, "-")),
+            predicate_str: predicate.to_owned(),
+            splitter_str: String::new(),
+            predicate: pred_reg,
+            splitter: None
+        })
     }
 
     pub fn find(&self, log: &str) -> Option<ComplexMatch> {
         match self.predicate.captures(log) {
             None => None,
             Some(cap) => {
-                let inner = cap.name("inner").unwrap();
+                let inner = match cap.name("inner") {
+                    Some(inner) => inner,
+                    None => return None
+                };
                 let mut identifiers: Vec<String> = Vec::new();
-                let split = self.splitter.split(inner);
-                for m in split {
-                    let mut iden = self.project_id.to_owned() + "-";
-                    let mut begin = false;
-                    for c in m.chars() {
-                        if c.is_digit(10) {
-                            begin = true;
-                            iden.push(c);
-                        } else if begin {
-                            break;
-                        }
+                let parts: Vec<&str> = match self.splitter {
+                    Some(ref splitter) => splitter.split(inner).collect(),
+                    None => vec![inner]
+                };
+                for m in parts {
+                    for key in self.key.captures_iter(m) {
+                        let project = key.name("project").unwrap();
+                        let number = key.name("number").unwrap();
+                        identifiers.push(
+                            project.to_uppercase() + "-" + number
+                        );
                     }
-
-                    identifiers.push(iden);
                 }
                 Some(identifiers)
             }
@@ -99,44 +245,193 @@ impl ComplexPattern {
     }
 }
 
+/// How the keys are found in a line of a commit message.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MatchMode {
+    /// Only the keys at the start of the line, in one of the patterns, e.g.
+    /// `[FOO-1 & FOO-2] Fix the thing`.
+    Strict,
+    /// Every key in the line, e.g. `Fix the crash (FOO-1)`. A key must be a
+    /// whole word, so `XFOO-1` and `FOO-1abc` aren't keys.
+    Loose
+}
+
+/// Finds the issue keys in commit subjects, e.g. `[FOO-1] Fix the thing`.
 pub struct TokenParser {
-    patterns: Vec<ComplexPattern>
+    patterns: Vec<ComplexPattern>,
+    // Any key of the projects, as a whole word, to find those listed in
+    // trailers or anywhere in a line in loose mode.
+    key: Regex,
+    trailer: Regex,
+    full_message: bool,
+    mode: MatchMode
 }
 
 impl TokenParser {
-    pub fn new(project_id: &str) -> TokenParser {
+    /// Finds the issues of any of the projects given, e.g. [foo-1 & bar-2].
+    pub fn new(project_ids: &[String]) -> TokenParser {
         TokenParser {
+            key: Regex::new(&format!(
+                "(?i)\\b(?P<project>{})-(?P<number>[0-9]+)\\b",
+                ComplexPattern::projects(project_ids))).unwrap(),
+            trailer: Regex::new(TRAILERS).unwrap(),
+            full_message: false,
+            mode: MatchMode::Strict,
             patterns: vec![
                 // [foo-1 & foo-2] foobar
-                ComplexPattern::new(project_id, "-", "\\[", "\\]",
+                ComplexPattern::new(project_ids, "-", "\\[", "\\]",
                     "[ ]+&[ ]+"),
                 // (foo-1 foo-2) foobar
-                ComplexPattern::new(project_id, "-", "[(]", "[)]", "[ ]+"),
-                ComplexPattern::new(project_id, "-", "[(]", "[)]",
+                ComplexPattern::new(project_ids, "-", "[(]", "[)]", "[ ]+"),
+                ComplexPattern::new(project_ids, "-", "[(]", "[)]",
                     "[ ]*,[ ]+"),
                 // foo-1 foo-2 foobar
-                ComplexPattern::new(project_id, "-", "", "", "[ ]+"),
+                ComplexPattern::new(project_ids, "-", "", "", "[ ]+"),
                 // foo-1, foo-2 foobar
-                ComplexPattern::new(project_id, "-", "", "", "[ ]*,[ ]+")
+                ComplexPattern::new(project_ids, "-", "", "", "[ ]*,[ ]+")
             ]
         }
     }
 
+    /// Finds the keys with the custom patterns as well as the built-in ones.
+    /// Fails with a config error when there is no project key or one is
+    /// invalid, see `validate_project_keys`, when a pattern isn't a valid
+    /// regex, or a raw regex has no `inner` group.
+    pub fn with_patterns(project_ids: &[String], patterns: &[TokenPattern])
+            -> Result<TokenParser, Error> {
+        try!(validate_project_keys(project_ids));
+        let mut parser = TokenParser::new(project_ids);
+        for pattern in patterns {
+            parser.patterns.push(try!(match *pattern {
+                TokenPattern::Tags {
+                    ref open_tag,
+                    ref close_tag,
+                    ref separator,
+                    ref multi_separator
+                } => ComplexPattern::with_tags(project_ids, separator,
+                    open_tag, close_tag, multi_separator),
+                TokenPattern::Regex(ref regex) =>
+                    ComplexPattern::with_regex(project_ids, regex)
+            }));
+        }
+        Ok(parser)
+    }
+
+    /// When true, the keys are also looked for in the body of the commits:
+    /// at the start of its lines and in the `Refs:` and `Jira:` trailers.
+    pub fn with_full_message(self, full_message: bool) -> TokenParser {
+        TokenParser { full_message: full_message, ..self }
+    }
+
+    pub fn with_mode(self, mode: MatchMode) -> TokenParser {
+        TokenParser { mode: mode, ..self }
+    }
+
+    /// The keys found in the commit, in the order of the message.
+    pub fn tokens(&self, commit: &Commit) -> Vec<Token> {
+        let token = |location| move |key| Token {
+            key: key,
+            location: location
+        };
+        let mut tokens: Vec<Token> = self.parse_line(&commit.summary)
+            .into_iter()
+            .map(token(Location::Subject))
+            .collect();
+        if !self.full_message {
+            return tokens;
+        }
+        for line in commit.body.lines() {
+            // Squash merges list the commits as bullets, e.g. "* [FOO-1] msg".
+            let line = line.trim_start();
+            let line = if line.starts_with("* ") || line.starts_with("- ") {
+                &line[2..]
+            } else {
+                line
+            };
+            match self.trailer.captures(line) {
+                Some(trailer) => {
+                    let keys = trailer.name("keys").unwrap();
+                    tokens.extend(self.keys(keys)
+                        .into_iter()
+                        .map(token(Location::Trailer)));
+                },
+                None => tokens.extend(self.parse_line(line)
+                    .into_iter()
+                    .map(token(Location::Body)))
+            }
+        }
+        tokens
+    }
+
+    /// Every key found in the commits once, with the commits mentioning it.
+    /// The keys are in the order they are first found.
+    pub fn find<'c>(&self, commits: &'c [Commit]) -> Vec<Mentions<'c>> {
+        let mut found: Vec<Mentions<'c>> = Vec::new();
+        for commit in commits {
+            for token in self.tokens(commit) {
+                let i = match found.iter().position(|m| m.key == token.key) {
+                    Some(i) => i,
+                    None => {
+                        found.push(Mentions {
+                            key: token.key,
+                            commits: Vec::new(),
+                            found_in: Vec::new()
+                        });
+                        found.len() - 1
+                    }
+                };
+                let mentions = &mut found[i];
+                if mentions.commits.last() != Some(&commit) {
+                    mentions.commits.push(commit);
+                }
+                if !mentions.found_in.contains(&token.location) {
+                    mentions.found_in.push(token.location);
+                }
+            }
+        }
+        found
+    }
+
+    /// The keys found in the logs, one commit subject per line, each key
+    /// once. Keys are uppercased, e.g. `FOO-1`.
     pub fn parse(&self, logs: &str) -> Vec<String> {
         let mut jira_tokens: Vec<String> = Vec::new();
         for log in logs.lines() {
-            let mut tokens: Vec<String> = Vec::new();
-            for pattern in &self.patterns {
-                if let Some(found) = pattern.find(log) {
-                    if tokens.len() < found.len() {
-                        tokens = found;
-                    }
+            for token in self.parse_line(log) {
+                if !jira_tokens.contains(&token) {
+                    jira_tokens.push(token);
                 }
             }
-            jira_tokens.extend(tokens);
         }
         jira_tokens
     }
+
+    // Every key in the text, uppercased.
+    fn keys(&self, text: &str) -> Vec<String> {
+        self.key.captures_iter(text)
+            .map(|key| {
+                key.name("project").unwrap().to_uppercase() + "-" +
+                    key.name("number").unwrap()
+            })
+            .collect()
+    }
+
+    // The tokens of a single commit subject. In strict mode, they are taken
+    // from the pattern which finds the most of them.
+    fn parse_line(&self, log: &str) -> Vec<String> {
+        if self.mode == MatchMode::Loose {
+            return self.keys(log);
+        }
+        let mut tokens: Vec<String> = Vec::new();
+        for pattern in &self.patterns {
+            if let Some(found) = pattern.find(log) {
+                if tokens.len() < found.len() {
+                    tokens = found;
+                }
+            }
+        }
+        tokens
+    }
 }
 
 #[test]
@@ -164,13 +459,15 @@ fn predicate_pattern() {
 #[test]
 fn complex_pattern() {
     // e.g.
-    let pat = ComplexPattern::new("foo", "-", "\\[", "\\]", "[ ]+&[ ]+");
+    let pat = ComplexPattern::new(&[String::from("foo")], "-", "\\[", "\\]",
+        "[ ]+&[ ]+");
     let found = pat.find("[foo-20]");
     assert!(found.is_some(), "found is not some");
     assert_eq!(found.unwrap(), ["FOO-20"]);
     assert!(pat.find("[foo ]").is_none(), "[foo ] isnt none");
     assert_eq!(pat.find("[foo-9 & foo-10]").unwrap(), ["FOO-9", "FOO-10"]);
-    let pat2 = ComplexPattern::new("hello", "-", "[(]", "[)]", "[ ]+");
+    let pat2 = ComplexPattern::new(&[String::from("hello")], "-", "[(]",
+        "[)]", "[ ]+");
     assert_eq!(
         pat2.find("(hello-10 hello-2) YOLO").unwrap(),
         ["HELLO-10", "HELLO-2"]
@@ -179,7 +476,7 @@ fn complex_pattern() {
 
 #[test]
 fn token_parser() {
-    let parser = TokenParser::new("foo");
+    let parser = TokenParser::new(&[String::from("foo")]);
     let logs = "[foo-1] hello world
         (foo-2, foo-3) lorem ipsum
         foo-4 tisk tisk
@@ -193,3 +490,126 @@ fn token_parser() {
         .collect();
     assert_eq!(jira_tokens, compare);
 }
+
+#[test]
+fn multiple_projects() {
+    let parser = TokenParser::new(&[String::from("api"), String::from("WEB")]);
+    let logs = "[API-12 & WEB-40] hello world
+        (web-2, api-3) lorem ipsum
+        foo-4 tisk tisk
+        WEB-5 yep";
+
+    assert_eq!(
+        parser.parse(logs),
+        ["API-12", "WEB-40", "WEB-2", "API-3", "WEB-5"]
+    );
+}
+
+#[test]
+fn commit_mentions() {
+    let parser = TokenParser::new(&[String::from("EX")]);
+    let commit = |sha, summary| ::git::test_commit(sha, summary, "");
+    let commits = [
+        commit("c3", "[EX-1] fix again"),
+        commit("c2", "[EX-2 & EX-2] other"),
+        commit("c1", "[ex-1] first")
+    ];
+    let mentions = parser.find(&commits);
+    assert_eq!(mentions.len(), 2);
+    assert_eq!(mentions[0].key, "EX-1");
+    assert_eq!(mentions[0].commits, [&commits[0], &commits[2]]);
+    assert_eq!(mentions[0].found_in, [Location::Subject]);
+    assert_eq!(mentions[1].key, "EX-2");
+    assert_eq!(mentions[1].commits, [&commits[1]]);
+    assert_eq!(parser.parse("[EX-1] a\n[EX-2 & ex-1] b"), ["EX-1", "EX-2"]);
+}
+
+#[test]
+fn custom_patterns() {
+    let tags = |open: &str, close: &str, multi: &str| TokenPattern::Tags {
+        open_tag: open.to_owned(),
+        close_tag: close.to_owned(),
+        separator: String::from("-"),
+        multi_separator: multi.to_owned()
+    };
+    let patterns = [
+        tags("", ":", "/"),
+        tags("feat\\(", "\\):", ","),
+        TokenPattern::Regex(String::from("^[ ]*fixes #(?P<inner>[^ ]+)"))
+    ];
+    let parser = TokenParser::with_patterns(&[String::from("FOO")],
+        &patterns).unwrap();
+    let logs = "FOO-12: msg
+        FOO-13/FOO-14: msg
+        feat(FOO-15,foo-16): msg
+        fixes #FOO-17+BAR-1+FOO-18 msg";
+    assert_eq!(parser.parse(logs),
+        ["FOO-12", "FOO-13", "FOO-14", "FOO-15", "FOO-16", "FOO-17", "FOO-18"]);
+
+    let invalid = |pattern: TokenPattern| {
+        TokenParser::with_patterns(&[String::from("FOO")], &[pattern])
+            .err()
+            .map(|e| e.exit_code())
+    };
+    assert_eq!(invalid(tags("(", "", " ")), Some(2));
+    assert_eq!(invalid(tags("", "", "[")), Some(2));
+    assert_eq!(invalid(TokenPattern::Regex(String::from("^FOO-[0-9]+"))),
+        Some(2));
+}
+
+#[test]
+fn full_messages() {
+    let commit = ::git::test_commit("c1", "Squashed the branch (#12)",
+        "* [EX-1] first change
+* second change
+
+EX-2 third change
+Mentions EX-9 in the middle.
+
+Refs: EX-3, ex-4
+jira: EX-5
+Signed-off-by: Someone");
+    let parser = TokenParser::new(&[String::from("EX")]);
+    assert!(parser.tokens(&commit).is_empty());
+
+    let parser = parser.with_full_message(true);
+    let tokens = parser.tokens(&commit);
+    let found: Vec<(&str, &str)> = tokens
+        .iter()
+        .map(|t| (&t.key[..], t.location.name()))
+        .collect();
+    assert_eq!(found, [("EX-1", "body"), ("EX-2", "body"),
+        ("EX-3", "trailer"), ("EX-4", "trailer"), ("EX-5", "trailer")]);
+    assert_eq!(parser.find(&[commit.clone()]).len(), 5);
+}
+
+#[test]
+fn loose_mode() {
+    let parser = TokenParser::new(&[String::from("FOO"), String::from("BAR")]);
+    let logs = "Fix crash in login (FOO-12)
+        Merge foo-13 follow-up, see BAR-1.
+        XFOO-14 FOO-15abc FOO-16_x FOO-
+        [FOO-17] strict";
+    assert_eq!(parser.parse(logs), ["FOO-17"]);
+    let parser = parser.with_mode(MatchMode::Loose);
+    assert_eq!(parser.parse(logs),
+        ["FOO-12", "FOO-13", "BAR-1", "FOO-17"]);
+}
+
+#[test]
+fn project_keys() {
+    assert!(validate_project_key("FOO").is_ok());
+    assert!(validate_project_key("FOO_2").is_ok());
+    for key in &["foo", "F", "2FOO", "FOO-1", "FO.O", "(FOO", ""] {
+        assert_eq!(validate_project_key(key).unwrap_err().exit_code(), 2);
+    }
+    let invalid = [String::from("EX"), String::from("E(X")];
+    assert!(TokenParser::with_patterns(&invalid, &[]).is_err());
+    assert_eq!(TokenParser::with_patterns(&[], &[]).err().unwrap()
+        .exit_code(), 2);
+
+    // Not valid keys, but they don't make the parser panic.
+    let parser = TokenParser::new(&[String::from("E(X"), String::from("A.B")]);
+    assert_eq!(parser.parse("[E(X-1 & A.B-2] msg\n[AXB-3] msg"),
+        ["E(X-1", "A.B-2"]);
+}